@@ -0,0 +1,368 @@
+//! HTTP clients of the lookup backends
+
+use crate::config::{BackendConfig, Config};
+use crate::dict::DictClient;
+use crate::extract::ScraperRules;
+use crate::retry;
+use crate::ydclient::BoxError;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, REFERER, USER_AGENT};
+use reqwest::{Client, ClientBuilder, RequestBuilder};
+use reqwest::{Proxy, Url};
+use std::env::var;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Built-in endpoint of a backend, used unless the config file overrides it
+struct Endpoint {
+    name: &'static str,
+    url: &'static str,
+    headers: &'static [(HeaderName, &'static str)],
+}
+
+const SCRAPER: Endpoint = Endpoint {
+    name: "scraper",
+    url: "https://www.youdao.com/result",
+    headers: &[
+        (REFERER, "https://www.youdao.com"),
+        (
+            USER_AGENT,
+            "Mozilla/5.0 (X11; AOSC OS; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/117.0",
+        ),
+    ],
+};
+
+const OPENAPI: Endpoint = Endpoint {
+    name: "openapi",
+    url: "https://openapi.youdao.com/api",
+    headers: &[],
+};
+
+/// Query of the result page of `word`, as the scraper looks it up
+pub fn result_query(word: &str) -> [(&'static str, &str); 2] {
+    [("word", word), ("lang", "en")]
+}
+
+/// Result page of `word` on the scraper backend at `base`, e.g. youdao.com
+pub fn result_url(base: &Url, word: &str) -> Url {
+    let mut url = base.clone();
+    url.query_pairs_mut().extend_pairs(result_query(word));
+    url
+}
+
+/// Network settings given on the command line
+#[derive(Debug, Clone)]
+pub struct NetOptions {
+    pub connect_timeout: Duration,
+    /// longest wait for any part of an answer, once connected
+    pub read_timeout: Duration,
+    /// proxy for every backend, overriding `[proxy] url` of the config file
+    pub proxy: Option<String>,
+    /// hosts reached without a proxy, in addition to `[proxy] no_proxy`
+    pub no_proxy: Vec<String>,
+}
+
+impl Default for NetOptions {
+    fn default() -> NetOptions {
+        NetOptions {
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(10),
+            proxy: None,
+            no_proxy: vec![],
+        }
+    }
+}
+
+/// A backend: its HTTP client, base URL and the headers sent with every request
+#[derive(Clone)]
+pub struct Backend {
+    /// name used in logs, errors and the config file
+    pub name: &'static str,
+    pub client: Client,
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub connect_timeout: Duration,
+    /// longest wait for any part of an answer, once connected
+    pub read_timeout: Duration,
+}
+
+impl Backend {
+    fn new(endpoint: &Endpoint, client: Client) -> Backend {
+        let opts = NetOptions::default();
+        Backend {
+            name: endpoint.name,
+            client,
+            url: Url::parse(endpoint.url).unwrap(),
+            headers: endpoint
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+                .collect(),
+            connect_timeout: opts.connect_timeout,
+            read_timeout: opts.read_timeout,
+        }
+    }
+
+    fn configure(
+        endpoint: &Endpoint,
+        backend: &BackendConfig,
+        config: &Config,
+        opts: &NetOptions,
+    ) -> Result<Backend, Box<dyn Error>> {
+        let mut result = Backend::new(endpoint, build_client(backend, config, opts)?);
+        result.connect_timeout = opts.connect_timeout;
+        result.read_timeout = opts.read_timeout;
+        let context = |e: &dyn Error| format!("[backends.{}]: {}", endpoint.name, e);
+
+        if let Some(url) = &backend.url {
+            result.url = Url::parse(url).map_err(|e| context(&e))?;
+        }
+        if let Some(user_agent) = &backend.user_agent {
+            let value = HeaderValue::from_str(user_agent).map_err(|e| context(&e))?;
+            result.headers.insert(USER_AGENT, value);
+        }
+        for (name, value) in &backend.headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| context(&e))?;
+            let value = HeaderValue::from_str(value).map_err(|e| context(&e))?;
+            result.headers.insert(name, value);
+        }
+
+        Ok(result)
+    }
+
+    /// Name and origin of the backend, so that a mirror is told apart from the public endpoint
+    pub fn id(&self) -> String {
+        format!(
+            "{} ({})",
+            self.name,
+            self.url.origin().ascii_serialization()
+        )
+    }
+
+    /// GET the base URL with `query` appended
+    pub fn get(&self, query: &[(&str, &str)]) -> RequestBuilder {
+        let mut url = self.url.clone();
+        url.query_pairs_mut().extend_pairs(query.iter());
+        self.client.get(url).headers(self.headers.clone())
+    }
+
+    /// Send the request built by `request`, with retries, and read the answer
+    pub(crate) async fn fetch<F>(&self, request: F) -> Result<String, BoxError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let id = self.id();
+        // the answer starts once connected, which takes up to `connect_timeout`
+        let answer_timeout = self.connect_timeout + self.read_timeout;
+        let response = retry::send(&id, answer_timeout, request).await?;
+        retry::read(&id, self.read_timeout, response).await
+    }
+
+    /// POST `form` to the base URL
+    pub fn post_form(&self, form: &[(&str, &str)]) -> RequestBuilder {
+        self.client
+            .post(self.url.clone())
+            .headers(self.headers.clone())
+            .form(form)
+    }
+}
+
+/// All backends of a session, each with its own client so that it can use its own proxy
+#[derive(Clone)]
+pub struct Backends {
+    /// the youdao.com result page
+    pub scraper: Backend,
+    /// openapi.youdao.com
+    pub openapi: Backend,
+    /// extraction rules for the pages of `scraper`
+    pub rules: Arc<ScraperRules>,
+    /// DICT server queried along with Youdao, if configured
+    pub dict: Option<DictClient>,
+}
+
+impl Backends {
+    /// Use the same client and the built-in endpoints for every backend
+    pub fn with_client(client: Client) -> Backends {
+        Backends {
+            scraper: Backend::new(&SCRAPER, client.clone()),
+            openapi: Backend::new(&OPENAPI, client),
+            rules: ScraperRules::embedded(),
+            dict: None,
+        }
+    }
+
+    pub fn new(config: &Config, opts: &NetOptions) -> Result<Backends, Box<dyn Error>> {
+        Ok(Backends {
+            scraper: Backend::configure(&SCRAPER, &config.backends.scraper, config, opts)?,
+            openapi: Backend::configure(&OPENAPI, &config.backends.openapi, config, opts)?,
+            rules: ScraperRules::load(config)?,
+            dict: config
+                .dict
+                .as_ref()
+                .map(|dict| DictClient::new(dict, opts.read_timeout)),
+        })
+    }
+
+    /// Every Youdao backend, in the order lookups try them; `dict` is not
+    /// one, as its definitions are merged into the results of `lookup`
+    pub fn all(&self) -> [&Backend; 2] {
+        [&self.scraper, &self.openapi]
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ProxyChoice<'a> {
+    /// nothing configured, use the proxy from the environment, if any
+    System,
+    /// bypass every proxy
+    Direct,
+    /// the `[proxy] url` of the config file, which its `username` and
+    /// `password` go with
+    Global(&'a str),
+    /// a proxy of the backend or of the command line
+    Url(&'a str),
+}
+
+/// Pick the proxy of a backend: its own setting first, then `--proxy`, then the config file
+fn proxy_choice<'a>(
+    backend: &'a BackendConfig,
+    config: &'a Config,
+    opts: &'a NetOptions,
+) -> ProxyChoice<'a> {
+    match backend.proxy.as_deref().or(opts.proxy.as_deref()) {
+        Some("direct") | Some("none") => ProxyChoice::Direct,
+        Some(url) => ProxyChoice::Url(url),
+        None => match config.proxy.url.as_deref() {
+            None => ProxyChoice::System,
+            Some("direct") | Some("none") => ProxyChoice::Direct,
+            Some(url) => ProxyChoice::Global(url),
+        },
+    }
+}
+
+/// Whether `host` is `entry` or one of its subdomains; `*` matches every host
+fn bypasses(host: &str, no_proxy: &[String]) -> bool {
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim().trim_start_matches('.');
+        entry == "*"
+            || !entry.is_empty()
+                && (host.eq_ignore_ascii_case(entry)
+                    || host.len() > entry.len()
+                        && host.to_ascii_lowercase().ends_with(&format!(".{}", entry)))
+    })
+}
+
+fn env_proxy() -> Option<String> {
+    ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+        .iter()
+        .find_map(|name| var(name).ok().filter(|v| !v.is_empty()))
+}
+
+fn build_client(
+    backend: &BackendConfig,
+    config: &Config,
+    opts: &NetOptions,
+) -> Result<Client, Box<dyn Error>> {
+    // reqwest only bounds whole requests, so reads are bounded by
+    // `retry::send` and `retry::read`
+    let builder = ClientBuilder::new().connect_timeout(opts.connect_timeout);
+
+    let mut no_proxy = config.proxy.no_proxy.clone();
+    no_proxy.extend(opts.no_proxy.iter().cloned());
+
+    let env_proxy = env_proxy();
+    let choice = proxy_choice(backend, config, opts);
+    let url = match choice {
+        ProxyChoice::Direct => return Ok(builder.no_proxy().build()?),
+        ProxyChoice::Url(url) | ProxyChoice::Global(url) => url,
+        // reqwest would use the environment proxy by itself, but without
+        // honouring our no_proxy list
+        ProxyChoice::System => match env_proxy.as_deref() {
+            Some(url) if !no_proxy.is_empty() => {
+                if let Ok(env) = var("NO_PROXY").or_else(|_| var("no_proxy")) {
+                    no_proxy.extend(env.split(',').map(str::to_owned));
+                }
+                url
+            }
+            _ => return Ok(builder.build()?),
+        },
+    };
+
+    let mut url = Url::parse(url).map_err(|e| format!("invalid proxy URL {}: {}", url, e))?;
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(format!(
+            "unsupported proxy scheme {}, expected http, https or socks5",
+            url.scheme()
+        )
+        .into());
+    }
+    // the credentials of the global proxy are not for another one
+    if url.username().is_empty() && matches!(choice, ProxyChoice::Global(_)) {
+        if let Some(username) = &config.proxy.username {
+            url.set_username(username)
+                .and_then(|_| url.set_password(config.proxy.password.as_deref()))
+                .map_err(|_| format!("proxy URL {} cannot carry credentials", url))?;
+        }
+    }
+
+    let proxy = Proxy::custom(move |target| match target.host_str() {
+        Some(host) if bypasses(host, &no_proxy) => None,
+        _ => Some(url.clone()),
+    });
+
+    Ok(builder.proxy(proxy).build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bypasses() {
+        let no_proxy = vec!["example.com".to_owned(), ".internal".to_owned()];
+        assert!(bypasses("example.com", &no_proxy));
+        assert!(bypasses("www.Example.com", &no_proxy));
+        assert!(bypasses("wiki.internal", &no_proxy));
+        assert!(!bypasses("notexample.com", &no_proxy));
+        assert!(!bypasses("openapi.youdao.com", &no_proxy));
+        assert!(bypasses("openapi.youdao.com", &["*".to_owned()]));
+    }
+
+    #[test]
+    fn test_proxy_choice() {
+        let config: Config = toml::from_str(
+            r#"
+            [proxy]
+            url = "socks5://corp:1080"
+
+            [backends.openapi]
+            proxy = "direct"
+            "#,
+        )
+        .unwrap();
+        let mut opts = NetOptions::default();
+
+        assert_eq!(
+            ProxyChoice::Global("socks5://corp:1080"),
+            proxy_choice(&config.backends.scraper, &config, &opts)
+        );
+        assert_eq!(
+            ProxyChoice::Direct,
+            proxy_choice(&config.backends.openapi, &config, &opts)
+        );
+
+        opts.proxy = Some("http://cli:3128".to_owned());
+        assert_eq!(
+            ProxyChoice::Url("http://cli:3128"),
+            proxy_choice(&config.backends.scraper, &config, &opts)
+        );
+        assert_eq!(
+            ProxyChoice::System,
+            proxy_choice(
+                &Config::default().backends.scraper,
+                &Config::default(),
+                &NetOptions::default()
+            )
+        );
+    }
+}
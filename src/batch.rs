@@ -0,0 +1,377 @@
+//! batch lookup of word lists with a bounded worker pool
+
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::str::FromStr;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+use ydcv::backend::Backends;
+use ydcv::lang::Lang;
+use ydcv::retry::RetryErr;
+use ydcv::ydclient::{YdClient, YdClientErr};
+use ydcv::ydresponse::YdResponse;
+
+/// Output format of batch results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchFormat {
+    Jsonl,
+    Tsv,
+    Markdown,
+}
+
+impl FromStr for BatchFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BatchFormat, String> {
+        match s {
+            "jsonl" => Ok(BatchFormat::Jsonl),
+            "tsv" => Ok(BatchFormat::Tsv),
+            "markdown" | "md" => Ok(BatchFormat::Markdown),
+            _ => Err(format!(
+                "unknown format '{}', expected one of: jsonl, tsv, markdown",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub struct BatchOptions {
+    #[structopt(
+        value_name = "FILE",
+        help = "file with one query per line, or - for stdin"
+    )]
+    input: String,
+
+    #[structopt(
+        short = "o",
+        long = "output",
+        help = "write results to this file instead of stdout"
+    )]
+    output: Option<String>,
+
+    #[structopt(
+        short = "F",
+        long = "format",
+        help = "[jsonl, tsv, markdown] output format",
+        default_value = "jsonl"
+    )]
+    format: BatchFormat,
+
+    #[structopt(
+        short = "j",
+        long = "jobs",
+        help = "number of concurrent lookups",
+        default_value = "4"
+    )]
+    jobs: usize,
+
+    #[structopt(
+        long = "rate",
+        help = "maximum lookups per second across all jobs",
+        default_value = "5"
+    )]
+    rate: f64,
+
+    #[structopt(long = "progress", help = "progress file (default: <output>.progress)")]
+    progress: Option<String>,
+
+    #[structopt(
+        long = "resume",
+        help = "skip the lines recorded in the progress file and append to the output \
+                (a run killed while writing may leave its last record twice)"
+    )]
+    resume: bool,
+}
+
+/// Spaces requests evenly so that all workers together stay under a rate
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(per_second: f64) -> RateLimiter {
+        RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block until the caller may send its next request
+    fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Result of looking up one input line
+type Outcome = Result<YdResponse, String>;
+
+/// Whether the circuit breaker skipped a backend during the lookup that
+/// failed with `err`, so that the word was not really looked up there
+fn circuit_open(err: &(dyn Error + 'static)) -> bool {
+    match err.downcast_ref::<YdClientErr>() {
+        Some(YdClientErr::NewAndOldAPIError(new_api_err, old_api_err)) => {
+            circuit_open(new_api_err.as_ref()) || circuit_open(old_api_err.as_ref())
+        }
+        _ => matches!(err.downcast_ref(), Some(RetryErr::CircuitOpen(_))),
+    }
+}
+
+/// Run `ydcv batch`
+pub fn run(
+    opts: &BatchOptions,
+    client: &Backends,
+    (from, to): (Lang, Lang),
+) -> Result<(), Box<dyn Error>> {
+    if opts.jobs == 0 || !(opts.rate > 0.0 && opts.rate.is_finite()) {
+        return Err("--jobs and --rate must be positive".into());
+    }
+
+    let lines = read_lines(&opts.input)?;
+
+    let progress_path = opts
+        .progress
+        .clone()
+        .or_else(|| opts.output.as_ref().map(|o| format!("{}.progress", o)));
+    if opts.resume && progress_path.is_none() {
+        return Err("--resume needs --output or --progress to know where it stopped".into());
+    }
+
+    let done = match (&progress_path, opts.resume) {
+        (Some(path), true) => read_progress(path)?,
+        _ => 0,
+    };
+
+    let mut out: Box<dyn Write> = match &opts.output {
+        Some(path) => Box::new(
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(opts.resume)
+                .truncate(!opts.resume)
+                .open(path)?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+    if done == 0 {
+        write_header(&mut out, opts.format)?;
+    }
+
+    let queue = Arc::new(Mutex::new(
+        lines
+            .iter()
+            .enumerate()
+            .skip(done)
+            .map(|(i, line)| (i, line.trim().to_owned()))
+            .collect::<Vec<_>>()
+            .into_iter(),
+    ));
+    let limiter = Arc::new(RateLimiter::new(opts.rate));
+    // with whether the lookup met an open circuit
+    let (tx, rx) = channel::<(usize, Option<Outcome>, bool)>();
+
+    let workers = (0..opts.jobs)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let limiter = Arc::clone(&limiter);
+            let tx = tx.clone();
+            let mut client = client.clone();
+            thread::spawn(move || loop {
+                let next = queue.lock().unwrap().next();
+                let Some((i, word)) = next else {
+                    break;
+                };
+                let (outcome, open) = if word.is_empty() {
+                    (None, false)
+                } else {
+                    limiter.wait();
+                    match client.lookup_word_lang(&word, from, to, false) {
+                        Ok(result) => (Some(Ok(result)), false),
+                        Err(err) => (Some(Err(err.to_string())), circuit_open(err.as_ref())),
+                    }
+                };
+                if tx.send((i, outcome, open)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(tx);
+
+    // results arrive out of order; write them in input order so that the
+    // progress file only needs to remember how many lines are finished
+    let mut pending = BTreeMap::new();
+    let mut next = done;
+    let mut outage = None;
+    'results: for (i, outcome, open) in rx {
+        pending.insert(i, (outcome, open));
+        while let Some((outcome, open)) = pending.remove(&next) {
+            // stop before the line, so that --resume looks it up again once
+            // the backends are back
+            if open {
+                outage = outcome.and_then(Result::err);
+                break 'results;
+            }
+            if let Some(outcome) = outcome {
+                write_record(&mut out, opts.format, lines[next].trim(), &outcome)?;
+            }
+            // a kill between the two writes repeats this record on --resume
+            out.flush()?;
+            next += 1;
+            if let Some(path) = &progress_path {
+                fs::write(path, format!("{}\n", next))?;
+            }
+        }
+    }
+
+    for worker in workers {
+        worker.join().map_err(|_| "batch worker panicked")?;
+    }
+
+    match outage {
+        Some(err) => Err(format!(
+            "stopped at line {}, the backends are unavailable: {}",
+            next + 1,
+            err
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+fn read_lines(input: &str) -> io::Result<Vec<String>> {
+    if input == "-" {
+        io::stdin().lock().lines().collect()
+    } else {
+        BufReader::new(File::open(input)?).lines().collect()
+    }
+}
+
+fn read_progress(path: &str) -> Result<usize, Box<dyn Error>> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(s.trim().parse()?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_header(out: &mut dyn Write, format: BatchFormat) -> io::Result<()> {
+    match format {
+        BatchFormat::Jsonl => Ok(()),
+        BatchFormat::Tsv => writeln!(out, "query\tphonetic\ttranslation\texplanations"),
+        BatchFormat::Markdown => {
+            writeln!(out, "| Query | Phonetic | Translation | Explanations |")?;
+            writeln!(out, "| --- | --- | --- | --- |")
+        }
+    }
+}
+
+fn write_record(
+    out: &mut dyn Write,
+    format: BatchFormat,
+    query: &str,
+    outcome: &Outcome,
+) -> io::Result<()> {
+    if format == BatchFormat::Jsonl {
+        let record = match outcome {
+            Ok(result) => json!({ "query": query, "result": result }),
+            Err(err) => json!({ "query": query, "error": err }),
+        };
+        return writeln!(out, "{}", record);
+    }
+
+    let fields = match outcome {
+        Ok(result) => {
+            let basic = result.basic();
+            [
+                basic
+                    .and_then(|b| b.phonetic())
+                    .unwrap_or_default()
+                    .to_owned(),
+                result.translation().unwrap_or_default().join("; "),
+                basic.map(|b| b.explains().join("; ")).unwrap_or_default(),
+            ]
+        }
+        Err(err) => [String::new(), String::new(), format!("error: {}", err)],
+    };
+
+    match format {
+        BatchFormat::Tsv => {
+            let clean = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+            writeln!(
+                out,
+                "{}\t{}",
+                clean(query),
+                fields.map(|f| clean(&f)).join("\t")
+            )
+        }
+        _ => {
+            let clean = |s: &str| s.replace('|', "\\|").replace(['\n', '\r'], " ");
+            writeln!(
+                out,
+                "| {} | {} |",
+                clean(query),
+                fields.map(|f| clean(&f)).join(" | ")
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_record_error() {
+        let outcome: Outcome = Err("boom | bang".to_owned());
+
+        let mut out = vec![];
+        write_record(&mut out, BatchFormat::Jsonl, "a|b", &outcome).unwrap();
+        write_record(&mut out, BatchFormat::Tsv, "a\tb", &outcome).unwrap();
+        write_record(&mut out, BatchFormat::Markdown, "a|b", &outcome).unwrap();
+        assert_eq!(
+            r#"{"error":"boom | bang","query":"a|b"}
+a b			error: boom | bang
+| a\|b |  |  | error: boom \| bang |
+"#,
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_circuit_open() {
+        let open = || Box::new(RetryErr::CircuitOpen("scraper".to_owned()));
+        assert!(circuit_open(open().as_ref()));
+        assert!(circuit_open(&YdClientErr::NewAndOldAPIError(
+            Box::new(YdClientErr::NewApiValueError),
+            open(),
+        )));
+        assert!(!circuit_open(&YdClientErr::NewAndOldAPIError(
+            Box::new(YdClientErr::NewApiValueError),
+            Box::new(YdClientErr::ApiError("108".to_owned())),
+        )));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(100.0);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
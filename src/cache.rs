@@ -0,0 +1,117 @@
+//! in-memory cache of lookup results, shared by long-running modes
+
+use crate::lang::Lang;
+use crate::ydresponse::YdResponse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+type Key = (String, Lang, Lang);
+
+/// Lookup results by word and language pair, each kept for a limited time
+pub struct Cache {
+    entries: Mutex<HashMap<Key, (Instant, YdResponse)>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl Cache {
+    /// A cache of at most `capacity` results, each fresh for `ttl`
+    pub fn new(capacity: usize, ttl: Duration) -> Cache {
+        Cache {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            ttl,
+        }
+    }
+
+    pub fn get(&self, word: &str, from: Lang, to: Lang) -> Option<YdResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (word.to_owned(), from, to);
+        match entries.get(&key) {
+            Some((added, response)) if added.elapsed() < self.ttl => Some(response.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Store `response`, evicting the oldest result when the cache is full
+    pub fn insert(&self, word: &str, from: Lang, to: Lang, response: YdResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let key = (word.to_owned(), from, to);
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (added, _))| *added)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, (Instant::now(), response));
+    }
+
+    /// The cached result for `word`, or else the result of `lookup`, cached
+    /// unless it is an error
+    pub fn get_or_lookup<F, E>(
+        &self,
+        word: &str,
+        from: Lang,
+        to: Lang,
+        lookup: F,
+    ) -> Result<YdResponse, E>
+    where
+        F: FnOnce() -> Result<YdResponse, E>,
+    {
+        if let Some(response) = self.get(word, from, to) {
+            return Ok(response);
+        }
+        let response = lookup()?;
+        self.insert(word, from, to, response.clone());
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(word: &str) -> YdResponse {
+        YdResponse::new_raw(format!(r#"{{"query":"{}","errorCode":0}}"#, word)).unwrap()
+    }
+
+    #[test]
+    fn test_cache_evicts_oldest() {
+        let cache = Cache::new(2, Duration::from_secs(60));
+        cache.insert("one", Lang::Auto, Lang::Auto, response("one"));
+        cache.insert("two", Lang::Auto, Lang::Auto, response("two"));
+        cache.insert("three", Lang::Auto, Lang::Auto, response("three"));
+
+        assert!(cache.get("one", Lang::Auto, Lang::Auto).is_none());
+        assert!(cache.get("two", Lang::Auto, Lang::Auto).is_some());
+        assert!(cache.get("three", Lang::En, Lang::ZhChs).is_none());
+        assert_eq!(
+            "three",
+            cache.get("three", Lang::Auto, Lang::Auto).unwrap().query()
+        );
+    }
+
+    #[test]
+    fn test_cache_expires() {
+        let cache = Cache::new(2, Duration::ZERO);
+        let result = cache.get_or_lookup("one", Lang::Auto, Lang::Auto, || {
+            Ok::<_, ()>(response("one"))
+        });
+        assert!(result.is_ok());
+        assert!(cache.get("one", Lang::Auto, Lang::Auto).is_none());
+        assert!(cache
+            .get_or_lookup("one", Lang::Auto, Lang::Auto, || Err(()))
+            .is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! user configuration file, `$XDG_CONFIG_HOME/ydcv/config.toml` by default
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Whole configuration file
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// extraction rules for the youdao.com page, see `src/extract.toml`
+    pub scraper_rules: Option<PathBuf>,
+    pub proxy: ProxyConfig,
+    pub backends: BackendsConfig,
+    /// DICT server whose definitions are shown along with Youdao results
+    pub dict: Option<DictConfig>,
+    /// color theme of the terminal, built-in or one of `themes`
+    pub theme: Option<String>,
+    /// pager of the explanations taller than the terminal, instead of
+    /// `$PAGER`; empty not to page them
+    pub pager: Option<String>,
+    /// user-defined themes by name
+    pub themes: BTreeMap<String, ThemeConfig>,
+    /// whether colored output links the headword and web references to
+    /// youdao.com, by default when the terminal seems to support it and the
+    /// output is not paged
+    pub hyperlinks: Option<bool>,
+    /// directory of the configuration file, where its companions such as
+    /// `templates` are looked for
+    #[serde(skip)]
+    pub dir: Option<PathBuf>,
+}
+
+/// Proxy used by every backend unless the backend overrides it
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// `http://`, `https://` or `socks5://` URL, credentials may be given inline
+    pub url: Option<String>,
+    /// credentials for `url` only, not for the proxies of the backends
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// hosts (and their subdomains) reached without the proxy, `*` for all
+    pub no_proxy: Vec<String>,
+}
+
+/// Per-backend settings
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendsConfig {
+    pub scraper: BackendConfig,
+    pub openapi: BackendConfig,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    /// proxy URL for this backend only, or `direct` to bypass any proxy
+    pub proxy: Option<String>,
+    /// base URL replacing the public endpoint, e.g. a mirror or a mock server
+    pub url: Option<String>,
+    pub user_agent: Option<String>,
+    /// extra request headers, replacing built-in ones of the same name such as `Referer`
+    pub headers: BTreeMap<String, String>,
+}
+
+/// A DICT (RFC 2229) server, e.g. a dictd serving in-house glossaries
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DictConfig {
+    pub host: String,
+    pub port: u16,
+    /// database to search, `*` for all of them
+    pub database: String,
+    /// `exact`, or a MATCH strategy of the server such as `prefix`
+    pub strategy: String,
+}
+
+impl Default for DictConfig {
+    fn default() -> DictConfig {
+        DictConfig {
+            host: "localhost".to_owned(),
+            port: 2628,
+            database: "*".to_owned(),
+            strategy: "exact".to_owned(),
+        }
+    }
+}
+
+/// A user-defined theme: styles such as `"bold #ff8700"` by element name,
+/// e.g. `headword` or `web-key`
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// theme giving the styles of the other elements, `dark` by default
+    pub base: Option<String>,
+    #[serde(flatten)]
+    pub styles: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Default directory of the configuration file and its companions
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("ydcv"))
+    }
+
+    /// Default location of the configuration file
+    pub fn default_path() -> Option<PathBuf> {
+        Config::default_dir().map(|d| d.join("config.toml"))
+    }
+
+    /// Load the configuration from `path`, or from the default location
+    ///
+    /// A missing file at the default location is not an error, as the
+    /// configuration file is optional.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match Config::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let mut config = match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => Config::default(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+        };
+        config.dir = path.parent().map(Path::to_owned);
+        Ok(config)
+    }
+}
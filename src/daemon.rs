@@ -0,0 +1,253 @@
+//! background daemon answering lookups on a Unix socket, and its client
+
+use crate::serve::CacheOptions;
+use serde::{Deserialize, Serialize};
+use serde_json::Error as SerdeError;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+use ydcv::backend::Backends;
+use ydcv::cache::Cache;
+use ydcv::lang::Lang;
+use ydcv::translate::Translation;
+use ydcv::ydclient::YdClient;
+use ydcv::ydresponse::YdResponse;
+
+/// How long the client waits for the daemon to answer
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(StructOpt)]
+pub struct DaemonOptions {
+    #[structopt(
+        long = "socket",
+        help = "socket to listen on (default: $YDCV_SOCKET or $XDG_RUNTIME_DIR/ydcv.sock)",
+        parse(from_os_str)
+    )]
+    socket: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    cache: CacheOptions,
+}
+
+/// One line sent to the daemon
+#[derive(Serialize, Deserialize)]
+struct Request {
+    q: String,
+    /// language codes, `auto` when missing
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// One line sent back: `{"ok": <result>}` or `{"error": "..."}`
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Reply {
+    Ok(YdResponse),
+    Error(String),
+}
+
+/// Socket shared by the daemon and the CLI
+pub fn default_socket() -> PathBuf {
+    if let Some(path) = env::var_os("YDCV_SOCKET") {
+        return PathBuf::from(path);
+    }
+    match dirs::runtime_dir() {
+        Some(dir) => dir.join("ydcv.sock"),
+        None => env::temp_dir().join(format!(
+            "ydcv-{}.sock",
+            env::var("USER").unwrap_or_else(|_| "user".to_owned())
+        )),
+    }
+}
+
+fn parse_lang(code: &Option<String>) -> Result<Lang, String> {
+    code.as_deref().unwrap_or("auto").parse()
+}
+
+fn answer(line: &str, client: &mut Backends, cache: &Cache) -> Reply {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => return Reply::Error(format!("invalid request: {}", err)),
+    };
+    let (from, to) = match (parse_lang(&request.from), parse_lang(&request.to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(err), _) | (_, Err(err)) => return Reply::Error(err),
+    };
+
+    match cache.get_or_lookup(&request.q, from, to, || {
+        client.lookup_word_lang(&request.q, from, to, false)
+    }) {
+        Ok(response) => Reply::Ok(response),
+        Err(err) => Reply::Error(err.to_string()),
+    }
+}
+
+/// Answer every request line of a connection until the client hangs up
+fn handle(stream: UnixStream, client: &mut Backends, cache: &Cache) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let reply = answer(&line?, client, cache);
+        writeln!(writer, "{}", serde_json::to_string(&reply)?)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn serve(listener: UnixListener, client: &Backends, cache: Arc<Cache>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let mut client = client.clone();
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    if let Err(err) = handle(stream, &mut client, &cache) {
+                        log::debug!("daemon connection failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => log::debug!("cannot accept a connection: {}", err),
+        }
+    }
+}
+
+/// Run `ydcv daemon` until the process is killed
+pub fn run(opts: &DaemonOptions, client: &Backends) -> Result<(), Box<dyn Error>> {
+    let path = opts.socket.clone().unwrap_or_else(default_socket);
+    let context = |e: &dyn Error| format!("{}: {}", path.display(), e);
+
+    if let Ok(metadata) = fs::symlink_metadata(&path) {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(format!("a daemon is already listening on {}", path.display()).into());
+        }
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()).into());
+        }
+        // left behind by a daemon that did not exit cleanly
+        fs::remove_file(&path).map_err(|e| context(&e))?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| context(&e))?;
+    }
+
+    let listener = UnixListener::bind(&path).map_err(|e| context(&e))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).map_err(|e| context(&e))?;
+    eprintln!("ydcv: listening on {}", path.display());
+
+    serve(listener, client, Arc::new(opts.cache.cache()));
+    Ok(())
+}
+
+/// Ask the daemon on `socket` to look up `word`; `None` when no daemon is listening
+fn ask(socket: &Path, word: &str, from: Lang, to: Lang) -> Option<Result<YdResponse, String>> {
+    let stream = UnixStream::connect(socket).ok()?;
+
+    let exchange = || -> Result<Reply, Box<dyn Error>> {
+        stream.set_read_timeout(Some(ANSWER_TIMEOUT))?;
+        let request = Request {
+            q: word.to_owned(),
+            from: Some(from.code().to_owned()),
+            to: Some(to.code().to_owned()),
+        };
+        writeln!(&stream, "{}", serde_json::to_string(&request)?)?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    };
+
+    Some(match exchange() {
+        Ok(Reply::Ok(response)) => Ok(response),
+        Ok(Reply::Error(err)) => Err(err),
+        Err(err) => Err(format!("daemon at {}: {}", socket.display(), err)),
+    })
+}
+
+/// Client that looks words up through the daemon when one is running, and
+/// directly otherwise
+pub struct DaemonClient {
+    socket: Option<PathBuf>,
+    direct: Backends,
+}
+
+impl DaemonClient {
+    /// Wrap `direct`, without asking any daemon unless `socket` is given
+    pub fn new(direct: Backends, socket: Option<PathBuf>) -> DaemonClient {
+        DaemonClient { socket, direct }
+    }
+}
+
+impl YdClient for DaemonClient {
+    fn lookup_word_lang(
+        &mut self,
+        word: &str,
+        from: Lang,
+        to: Lang,
+        raw: bool,
+    ) -> Result<YdResponse, Box<dyn Error>> {
+        // the daemon answers decoded responses, not the raw reply
+        if let Some(socket) = self.socket.as_ref().filter(|_| !raw) {
+            if let Some(result) = ask(socket, word, from, to) {
+                return result.map_err(Into::into);
+            }
+        }
+        self.direct.lookup_word_lang(word, from, to, raw)
+    }
+
+    fn decode_result(&mut self, result: &str) -> Result<YdResponse, SerdeError> {
+        self.direct.decode_result(result)
+    }
+
+    fn translate_text(
+        &mut self,
+        text: &str,
+        from: Lang,
+        to: Lang,
+    ) -> Result<Translation, Box<dyn Error>> {
+        self.direct.translate_text(text, from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn test_daemon_round_trip() {
+        let socket = env::temp_dir().join(format!("ydcv-test-{}.sock", process::id()));
+        let _ = fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let backends = Backends::with_client(reqwest::Client::new());
+        let cache = Arc::new(Cache::new(10, Duration::from_secs(60)));
+        let response = YdResponse::new_raw(
+            r#"{"query":"hello","errorCode":0,"translation":["你好"]}"#.to_owned(),
+        )
+        .unwrap();
+        cache.insert("hello", Lang::En, Lang::ZhChs, response);
+        let daemon = backends.clone();
+        thread::spawn(move || serve(listener, &daemon, cache));
+
+        let mut client = DaemonClient::new(backends, Some(socket.clone()));
+        let result = client
+            .lookup_word_lang("hello", Lang::En, Lang::ZhChs, false)
+            .unwrap();
+        assert_eq!(Some(&["你好".to_owned()][..]), result.translation());
+
+        let err = ask(&socket, "hello", Lang::En, Lang::Ja)
+            .unwrap()
+            .unwrap_err();
+        assert!(err.contains("only supports English <-> Chinese"), "{}", err);
+
+        fs::remove_file(&socket).unwrap();
+        assert!(ask(&socket, "hello", Lang::En, Lang::ZhChs).is_none());
+    }
+}
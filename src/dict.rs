@@ -0,0 +1,349 @@
+//! client of DICT (RFC 2229) servers, such as a dictd with in-house glossaries
+
+use crate::config::DictConfig;
+use crate::ydclient::BoxError;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+/// Most headwords defined after a MATCH with a strategy other than `exact`
+const MAX_MATCHES: usize = 5;
+
+/// A definition found on a DICT server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub word: String,
+    pub database: String,
+    pub description: String,
+    pub text: String,
+}
+
+/// A DICT server and how to query it
+#[derive(Debug, Clone)]
+pub struct DictClient {
+    pub host: String,
+    pub port: u16,
+    /// database to search, `*` for all of them
+    pub database: String,
+    /// `exact` defines the word itself, any other strategy defines what MATCH finds
+    pub strategy: String,
+    /// bound of a whole lookup, connection included
+    pub timeout: Duration,
+}
+
+/// Split a DICT line into words, honouring "double" and 'single' quotes and backslashes
+pub fn tokenize(line: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Some(words);
+        };
+        let mut word = String::new();
+        if first == '"' || first == '\'' {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '\\' => word.push(chars.next()?),
+                    c if c == first => break,
+                    c => word.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                match c {
+                    '\\' => word.push(chars.next()?),
+                    c => word.push(c),
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+/// Quote `s` as a single DICT word
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// An open connection, past the banner
+struct Connection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    async fn open(host: &str, port: u16) -> Result<Connection, BoxError> {
+        let (reader, writer) = TcpStream::connect((host, port)).await?.into_split();
+        let mut connection = Connection {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        connection.expect(220).await?;
+        Ok(connection)
+    }
+
+    async fn line(&mut self) -> Result<String, BoxError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err("the DICT server closed the connection".into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+    }
+
+    /// Code and rest of the next status line
+    async fn status(&mut self) -> Result<(u16, String), BoxError> {
+        let line = self.line().await?;
+        match line.get(..3).and_then(|code| code.parse().ok()) {
+            Some(code) => Ok((code, line[3..].trim().to_owned())),
+            None => Err(format!("unexpected answer from the DICT server: {}", line).into()),
+        }
+    }
+
+    async fn expect(&mut self, code: u16) -> Result<String, BoxError> {
+        match self.status().await? {
+            (c, rest) if c == code => Ok(rest),
+            (c, rest) => Err(format!("the DICT server answered {} {}", c, rest).into()),
+        }
+    }
+
+    /// A text block up to its final ".", without dot-stuffing
+    async fn text(&mut self) -> Result<String, BoxError> {
+        let mut lines = vec![];
+        loop {
+            let line = self.line().await?;
+            match line.strip_prefix('.') {
+                Some("") => return Ok(lines.join("\n")),
+                Some(rest) if rest.starts_with('.') => lines.push(rest.to_owned()),
+                _ => lines.push(line),
+            }
+        }
+    }
+
+    async fn send(&mut self, command: &str) -> Result<(), BoxError> {
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// `(database, headword)` pairs found by MATCH
+    async fn matches(
+        &mut self,
+        database: &str,
+        strategy: &str,
+        word: &str,
+    ) -> Result<Vec<(String, String)>, BoxError> {
+        self.send(&format!("MATCH {} {} {}", database, strategy, quote(word)))
+            .await?;
+        match self.status().await? {
+            (152, _) => {}
+            (552, _) => return Ok(vec![]),
+            (code, rest) => return Err(format!("MATCH failed: {} {}", code, rest).into()),
+        }
+        let matches = self
+            .text()
+            .await?
+            .lines()
+            .filter_map(|line| match tokenize(line)?.as_slice() {
+                [database, word] => Some((database.clone(), word.clone())),
+                _ => None,
+            })
+            .collect();
+        self.expect(250).await?;
+        Ok(matches)
+    }
+
+    async fn define(&mut self, database: &str, word: &str) -> Result<Vec<Definition>, BoxError> {
+        self.send(&format!("DEFINE {} {}", database, quote(word)))
+            .await?;
+        match self.status().await? {
+            (150, _) => {}
+            (552, _) => return Ok(vec![]),
+            (code, rest) => return Err(format!("DEFINE failed: {} {}", code, rest).into()),
+        }
+
+        let mut definitions = vec![];
+        loop {
+            match self.status().await? {
+                (151, rest) => {
+                    let words = tokenize(&rest).unwrap_or_default();
+                    let text = self.text().await?;
+                    definitions.push(Definition {
+                        word: words.first().cloned().unwrap_or_else(|| word.to_owned()),
+                        database: words.get(1).cloned().unwrap_or_default(),
+                        description: words.get(2).cloned().unwrap_or_default(),
+                        text,
+                    });
+                }
+                (250, _) => return Ok(definitions),
+                (code, rest) => return Err(format!("DEFINE failed: {} {}", code, rest).into()),
+            }
+        }
+    }
+}
+
+impl DictClient {
+    pub fn new(config: &DictConfig, timeout: Duration) -> DictClient {
+        DictClient {
+            host: config.host.clone(),
+            port: config.port,
+            database: config.database.clone(),
+            strategy: config.strategy.clone(),
+            timeout,
+        }
+    }
+
+    async_api! {
+        /// Definitions of `word` in the configured database
+        pub async fn define(&self, word: &str) -> Result<Vec<Definition>, BoxError> {
+            tokio::time::timeout(self.timeout, self.lookup(word))
+                .await
+                .map_err(|_| format!("the DICT server {}:{} timed out", self.host, self.port))?
+        }
+    }
+
+    async fn lookup(&self, word: &str) -> Result<Vec<Definition>, BoxError> {
+        let mut connection = Connection::open(&self.host, self.port).await?;
+        connection.send("CLIENT ydcv").await?;
+        connection.expect(250).await?;
+
+        let headwords = if self.strategy == "exact" {
+            vec![(self.database.clone(), word.to_owned())]
+        } else {
+            connection
+                .matches(&self.database, &self.strategy, word)
+                .await?
+        };
+
+        let mut definitions = vec![];
+        for (database, headword) in headwords.into_iter().take(MAX_MATCHES) {
+            definitions.extend(connection.define(&database, &headword).await?);
+        }
+
+        // the answer to QUIT does not matter any more
+        let _ = connection.send("QUIT").await;
+        Ok(definitions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatters::PlainFormatter;
+    use crate::nonblocking::block_on;
+    use crate::ydresponse::YdResponse;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve one connection like a dictd with a `glossary` database defining "ydcv"
+    fn stand_in() -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut out = stream.try_clone().unwrap();
+            let mut commands = vec![];
+            write!(out, "220 stand-in <> <1@test>\r\n").unwrap();
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                let answer = match tokenize(&line).unwrap()[0].as_str() {
+                    "CLIENT" => "250 ok\r\n",
+                    "MATCH" => "152 1 matches found\r\nglossary \"ydcv\"\r\n.\r\n250 ok\r\n",
+                    "DEFINE" if line.contains("\"ydcv\"") => concat!(
+                        "150 1 definitions retrieved\r\n",
+                        "151 \"ydcv\" glossary \"Team glossary\"\r\n",
+                        "ydcv\r\n",
+                        "  YouDao Console Version, our dictionary CLI\r\n",
+                        "..hidden dot\r\n",
+                        ".\r\n",
+                        "250 ok\r\n"
+                    ),
+                    "DEFINE" => "552 no match\r\n",
+                    _ => "221 bye\r\n",
+                };
+                commands.push(line);
+                out.write_all(answer.as_bytes()).unwrap();
+                if answer.starts_with("221") {
+                    break;
+                }
+            }
+            commands
+        });
+        (port, handle)
+    }
+
+    fn client(port: u16, strategy: &str) -> DictClient {
+        DictClient {
+            host: "127.0.0.1".to_owned(),
+            port,
+            database: "*".to_owned(),
+            strategy: strategy.to_owned(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            Some(vec![
+                "DEFINE".to_owned(),
+                "*".to_owned(),
+                "hot dog".to_owned()
+            ]),
+            tokenize("DEFINE * \"hot dog\"")
+        );
+        assert_eq!(
+            Some(vec!["MATCH".to_owned(), "it's".to_owned()]),
+            tokenize("  MATCH 'it\\'s'  ")
+        );
+        assert_eq!(None, tokenize("DEFINE * \"open"));
+    }
+
+    #[test]
+    fn test_define_exact() {
+        let (port, server) = stand_in();
+        let definitions = block_on(client(port, "exact").define("ydcv")).unwrap();
+        assert_eq!(
+            vec![Definition {
+                word: "ydcv".to_owned(),
+                database: "glossary".to_owned(),
+                description: "Team glossary".to_owned(),
+                text: "ydcv\n  YouDao Console Version, our dictionary CLI\n.hidden dot".to_owned(),
+            }],
+            definitions
+        );
+        assert_eq!(
+            vec!["CLIENT ydcv", "DEFINE * \"ydcv\"", "QUIT"],
+            server.join().unwrap()
+        );
+
+        let explained =
+            YdResponse::from_definitions("ydcv", definitions).explain(&PlainFormatter::new(false));
+        assert!(
+            explained.contains("  Team glossary:\n     ydcv\n       YouDao Console Version"),
+            "{}",
+            explained
+        );
+    }
+
+    #[test]
+    fn test_define_matches() {
+        let (port, server) = stand_in();
+        let definitions = block_on(client(port, "prefix").define("yd")).unwrap();
+        assert_eq!(1, definitions.len());
+        assert_eq!(
+            vec![
+                "CLIENT ydcv",
+                "MATCH * prefix \"yd\"",
+                "DEFINE glossary \"ydcv\"",
+                "QUIT"
+            ],
+            server.join().unwrap()
+        );
+    }
+}
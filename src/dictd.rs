@@ -0,0 +1,366 @@
+//! DICT protocol (RFC 2229) server, with each backend as a database
+
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::TcpListener;
+use std::process;
+use std::thread;
+use structopt::StructOpt;
+use ydcv::backend::Backends;
+use ydcv::dict::{quote, tokenize};
+use ydcv::formatters::{Entry, PlainFormatter};
+use ydcv::lang::Lang;
+use ydcv::ydresponse::YdResponse;
+
+/// The only strategy: Youdao cannot list headwords, so MATCH can only confirm one
+const STRATEGIES: &[(&str, &str)] = &[("exact", "Match headwords exactly")];
+
+#[derive(StructOpt)]
+pub struct DictdOptions {
+    #[structopt(
+        long = "listen",
+        value_name = "ADDR",
+        help = "address to listen on",
+        default_value = "127.0.0.1:2628"
+    )]
+    listen: String,
+}
+
+/// A database offered to clients
+struct Database {
+    name: String,
+    description: String,
+}
+
+/// One client connection; `lookup` looks a word up in a database
+struct Session<'a, L> {
+    databases: &'a [Database],
+    lookup: L,
+}
+
+/// Write `text` as a DICT text block: CRLF line endings, dot-stuffing and a final "."
+fn write_text(out: &mut dyn Write, text: &str) -> io::Result<()> {
+    for line in text.lines() {
+        if line.starts_with('.') {
+            write!(out, ".")?;
+        }
+        write!(out, "{}\r\n", line)?;
+    }
+    write!(out, ".\r\n")
+}
+
+impl<'a, L> Session<'a, L>
+where
+    L: FnMut(&str, &str) -> Result<YdResponse, Box<dyn Error>>,
+{
+    /// Databases named by `name`: all of them for `*` and `!`, or the one called so
+    fn select(&self, name: &str) -> Option<Vec<&'a Database>> {
+        match name {
+            "*" | "!" => Some(self.databases.iter().collect()),
+            _ => self
+                .databases
+                .iter()
+                .find(|db| db.name == name)
+                .map(|db| vec![db]),
+        }
+    }
+
+    /// Definitions of `word` in the databases named by `name`, or the status
+    /// line to send when there are none
+    fn lookup_all(
+        &mut self,
+        name: &str,
+        word: &str,
+    ) -> Result<Vec<(&'a Database, YdResponse)>, &'static str> {
+        let databases = self
+            .select(name)
+            .ok_or("550 invalid database, use \"SHOW DB\" for list of databases")?;
+
+        let mut definitions = vec![];
+        let mut failed = false;
+        for db in databases {
+            match (self.lookup)(&db.name, word) {
+                Ok(response) if Entry::new(&response).is_some() => {
+                    definitions.push((db, response));
+                    // `!` stops at the first database with a match
+                    if name == "!" {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    log::debug!("{} lookup of {} failed: {}", db.name, word, err);
+                    failed = true;
+                }
+            }
+        }
+
+        match (definitions.is_empty(), failed) {
+            (true, true) => Err("420 server temporarily unavailable"),
+            (true, false) => Err("552 no match"),
+            _ => Ok(definitions),
+        }
+    }
+
+    fn define(&mut self, out: &mut dyn Write, name: &str, word: &str) -> io::Result<()> {
+        let definitions = match self.lookup_all(name, word) {
+            Ok(definitions) => definitions,
+            Err(status) => return write!(out, "{}\r\n", status),
+        };
+
+        write!(out, "150 {} definitions retrieved\r\n", definitions.len())?;
+        for (db, response) in definitions {
+            write!(
+                out,
+                "151 {} {} {}\r\n",
+                quote(word),
+                db.name,
+                quote(&db.description)
+            )?;
+            write_text(out, &response.explain(&PlainFormatter::new(false)))?;
+        }
+        write!(out, "250 ok\r\n")
+    }
+
+    fn matches(
+        &mut self,
+        out: &mut dyn Write,
+        name: &str,
+        strategy: &str,
+        word: &str,
+    ) -> io::Result<()> {
+        if strategy != "." && !STRATEGIES.iter().any(|(s, _)| *s == strategy) {
+            return write!(
+                out,
+                "551 invalid strategy, use \"SHOW STRAT\" for a list of strategies\r\n"
+            );
+        }
+        let definitions = match self.lookup_all(name, word) {
+            Ok(definitions) => definitions,
+            Err(status) => return write!(out, "{}\r\n", status),
+        };
+
+        write!(out, "152 {} matches found\r\n", definitions.len())?;
+        let list = definitions
+            .iter()
+            .map(|(db, _)| format!("{} {}", db.name, quote(word)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        write_text(out, &list)?;
+        write!(out, "250 ok\r\n")
+    }
+
+    fn show(&self, out: &mut dyn Write, what: &str) -> io::Result<()> {
+        let (code, kind, list) = match what.to_ascii_uppercase().as_str() {
+            "DB" | "DATABASES" => (
+                110,
+                "databases",
+                self.databases
+                    .iter()
+                    .map(|db| format!("{} {}", db.name, quote(&db.description)))
+                    .collect::<Vec<_>>(),
+            ),
+            "STRAT" | "STRATEGIES" => (
+                111,
+                "strategies",
+                STRATEGIES
+                    .iter()
+                    .map(|(name, description)| format!("{} {}", name, quote(description)))
+                    .collect(),
+            ),
+            _ => return write!(out, "501 syntax error, illegal parameters\r\n"),
+        };
+
+        write!(out, "{} {} {} present\r\n", code, list.len(), kind)?;
+        write_text(out, &list.join("\n"))?;
+        write!(out, "250 ok\r\n")
+    }
+
+    /// Answer commands until the client sends QUIT or hangs up
+    fn run(&mut self, input: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "220 ydcv <> <{}@ydcv>\r\n", process::id())?;
+        out.flush()?;
+
+        let mut line = String::new();
+        while {
+            line.clear();
+            input.read_line(&mut line)? > 0
+        } {
+            let words = match tokenize(line.trim_end_matches(['\r', '\n'])) {
+                Some(words) if !words.is_empty() => words,
+                _ => {
+                    write!(out, "501 syntax error, illegal parameters\r\n")?;
+                    out.flush()?;
+                    continue;
+                }
+            };
+            let args = words[1..].iter().map(String::as_str).collect::<Vec<_>>();
+
+            match (words[0].to_ascii_uppercase().as_str(), args.as_slice()) {
+                ("DEFINE", [db, word]) => self.define(out, db, word)?,
+                ("MATCH", [db, strategy, word]) => self.matches(out, db, strategy, word)?,
+                ("SHOW", [what]) => self.show(out, what)?,
+                ("CLIENT", [_, ..]) => write!(out, "250 ok\r\n")?,
+                ("QUIT", []) => {
+                    write!(out, "221 bye\r\n")?;
+                    return out.flush();
+                }
+                ("DEFINE" | "MATCH" | "SHOW" | "CLIENT" | "QUIT", _) => {
+                    write!(out, "501 syntax error, illegal parameters\r\n")?
+                }
+                _ => write!(out, "500 unknown command\r\n")?,
+            }
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// A database per Youdao backend, then one for the DICT server if there is one
+fn databases(client: &Backends) -> Vec<Database> {
+    let mut databases = client
+        .all()
+        .iter()
+        .map(|backend| Database {
+            name: backend.name.to_owned(),
+            description: format!("Youdao, {}", backend.id()),
+        })
+        .collect::<Vec<_>>();
+    if let Some(dict) = &client.dict {
+        databases.push(Database {
+            name: "dict".to_owned(),
+            description: format!("DICT server {}:{}", dict.host, dict.port),
+        });
+    }
+    databases
+}
+
+/// Run `ydcv dictd` until the process is killed
+pub fn run(
+    opts: &DictdOptions,
+    client: &Backends,
+    (from, to): (Lang, Lang),
+) -> Result<(), Box<dyn Error>> {
+    let listener =
+        TcpListener::bind(&opts.listen).map_err(|e| format!("{}: {}", opts.listen, e))?;
+    eprintln!("ydcv: DICT server listening on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::debug!("cannot accept a connection: {}", err);
+                continue;
+            }
+        };
+        let client = client.clone();
+        thread::spawn(move || {
+            let databases = databases(&client);
+            let mut session = Session {
+                databases: &databases,
+                lookup: |db: &str, word: &str| client.lookup_on(db, word, from, to),
+            };
+
+            let result = stream.try_clone().and_then(|writer| {
+                session.run(&mut BufReader::new(stream), &mut BufWriter::new(writer))
+            });
+            if let Err(err) = result {
+                log::debug!("DICT connection failed: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use ydcv::config::DictConfig;
+    use ydcv::dict::DictClient;
+
+    fn session_output(commands: &str) -> String {
+        let databases = [
+            Database {
+                name: "scraper".to_owned(),
+                description: "Youdao web".to_owned(),
+            },
+            Database {
+                name: "openapi".to_owned(),
+                description: "Youdao API".to_owned(),
+            },
+        ];
+        let lookup = |db: &str, word: &str| match (db, word) {
+            ("openapi", "hello") => Ok(YdResponse::new_raw(
+                r#"{"query":"hello","errorCode":0,"translation":["你好"]}"#.to_owned(),
+            )
+            .unwrap()),
+            ("openapi", _) => Err("network is down".into()),
+            _ => Ok(YdResponse::new_raw(r#"{"query":"x","errorCode":1}"#.to_owned()).unwrap()),
+        };
+        let mut session = Session {
+            databases: &databases,
+            lookup,
+        };
+
+        let mut out = vec![];
+        session.run(&mut commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        let out = session_output(
+            "CLIENT test\r\nSHOW DB\r\nDEFINE * hello\r\nMATCH scraper exact hello\r\n\
+             MATCH * prefix hello\r\nDEFINE * bye\r\nDEFINE nope hello\r\nQUIT\r\n",
+        );
+        let expected = [
+            "220 ydcv <> <",
+            "250 ok",
+            "110 2 databases present",
+            "scraper \"Youdao web\"",
+            "openapi \"Youdao API\"",
+            ".",
+            "250 ok",
+            "150 1 definitions retrieved",
+            "151 \"hello\" openapi \"Youdao API\"",
+            "hello",
+            "  Translation:",
+            "    你好",
+            ".",
+            "250 ok",
+            "552 no match",
+            "551 invalid strategy",
+            "420 server temporarily unavailable",
+            "550 invalid database",
+            "221 bye",
+        ];
+        let lines = out.split("\r\n").collect::<Vec<_>>();
+        for (line, expected) in lines.iter().zip(expected) {
+            assert!(line.starts_with(expected), "{:?} in\n{}", expected, out);
+        }
+        assert_eq!(expected.len() + 1, lines.len(), "{}", out);
+    }
+
+    #[test]
+    fn test_databases() {
+        let mut client = Backends::with_client(reqwest::Client::new());
+        let names = |client: &Backends| {
+            databases(client)
+                .into_iter()
+                .map(|db| db.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["scraper", "openapi"], names(&client));
+
+        client.dict = Some(DictClient::new(
+            &DictConfig::default(),
+            Duration::from_secs(1),
+        ));
+        assert_eq!(vec!["scraper", "openapi", "dict"], names(&client));
+        assert_eq!(
+            "DICT server localhost:2628",
+            databases(&client)[2].description
+        );
+    }
+}
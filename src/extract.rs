@@ -0,0 +1,332 @@
+//! declarative extraction rules for the youdao.com result page
+
+use crate::config::Config;
+use once_cell::sync::Lazy;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Deserializer};
+use std::error::Error;
+use std::fs;
+use std::sync::Arc;
+
+/// Rules shipped with ydcv
+static DEFAULT_RULES: Lazy<Arc<ScraperRules>> = Lazy::new(|| {
+    Arc::new(toml::from_str(include_str!("extract.toml")).expect("embedded extract.toml is valid"))
+});
+
+/// Rules for the whole result page, with every selector compiled once
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ScraperRules {
+    no_data: Css,
+    en2zh: Direction,
+    zh2en: Direction,
+}
+
+/// Rules for one lookup direction
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Direction {
+    #[serde(default)]
+    labels: Labels,
+    phones: Option<Rule>,
+    phonetic: Option<Rule>,
+    pos: Option<Rule>,
+    explains: Rule,
+    translation: Rule,
+    web_keys: Rule,
+    web_values: Rule,
+}
+
+/// Labels preceding the UK and US phonetics in `phones`
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct Labels {
+    uk: Option<String>,
+    us: Option<String>,
+}
+
+/// Where the values of a rule come from
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Text {
+    /// every text node of every element
+    #[default]
+    Nodes,
+    /// the whole text of each element
+    Element,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Transform {
+    Trim,
+    /// remove every occurrence of the string
+    Remove(String),
+    /// keep what comes before the first occurrence of the string
+    Before(String),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    selector: Css,
+    #[serde(default)]
+    text: Text,
+    split: Option<String>,
+    #[serde(default)]
+    transforms: Vec<Transform>,
+    join: Option<String>,
+}
+
+/// A compiled CSS selector along with its source, for diagnostics
+#[derive(Debug)]
+struct Css {
+    source: String,
+    selector: Selector,
+}
+
+impl<'de> Deserialize<'de> for Css {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Css, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        let selector = Selector::parse(&source)
+            .map_err(|e| serde::de::Error::custom(format!("selector {}: {}", source, e)))?;
+        Ok(Css { source, selector })
+    }
+}
+
+/// How many elements a rule matched on a page
+#[derive(Debug, PartialEq)]
+pub struct RuleCheck {
+    pub rule: &'static str,
+    pub selector: String,
+    pub matches: usize,
+}
+
+/// Data found on a result page, before it is put into a `YdResponse`
+#[derive(Debug, Default, PartialEq)]
+pub struct Extracted {
+    pub translation: Option<String>,
+    pub explains: Vec<String>,
+    pub phonetic: Option<String>,
+    pub uk_phonetic: Option<String>,
+    pub us_phonetic: Option<String>,
+    pub web: Vec<(String, Vec<String>)>,
+}
+
+impl Rule {
+    /// Values of the rule, grouped by the element they come from
+    fn groups(&self, html: &Html) -> Vec<Vec<String>> {
+        let mut groups = html
+            .select(&self.selector.selector)
+            .map(|element| {
+                let texts = match self.text {
+                    Text::Nodes => element.text().map(str::to_owned).collect(),
+                    Text::Element => vec![element.text().collect::<String>()],
+                };
+                texts
+                    .iter()
+                    .flat_map(|text| match &self.split {
+                        Some(sep) => text.split(sep.as_str()).collect(),
+                        None => vec![text.as_str()],
+                    })
+                    .map(|value| self.transform(value))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(sep) = &self.join {
+            groups = vec![vec![groups.concat().join(sep)]];
+        }
+
+        groups
+    }
+
+    fn values(&self, html: &Html) -> Vec<String> {
+        self.groups(html).concat()
+    }
+
+    fn check(&self, rule: &'static str, html: &Html) -> RuleCheck {
+        RuleCheck {
+            rule,
+            selector: self.selector.source.clone(),
+            matches: html.select(&self.selector.selector).count(),
+        }
+    }
+
+    fn transform(&self, value: &str) -> String {
+        self.transforms
+            .iter()
+            .fold(value.to_owned(), |value, transform| match transform {
+                Transform::Trim => value.trim().to_owned(),
+                Transform::Remove(s) => value.replace(s.as_str(), ""),
+                Transform::Before(s) => value.split(s.as_str()).next().unwrap().to_owned(),
+            })
+    }
+}
+
+impl Direction {
+    fn check(&self, html: &Html) -> Vec<RuleCheck> {
+        [
+            ("phones", self.phones.as_ref()),
+            ("phonetic", self.phonetic.as_ref()),
+            ("pos", self.pos.as_ref()),
+            ("explains", Some(&self.explains)),
+            ("translation", Some(&self.translation)),
+            ("web_keys", Some(&self.web_keys)),
+            ("web_values", Some(&self.web_values)),
+        ]
+        .into_iter()
+        .filter_map(|(name, rule)| Some(rule?.check(name, html)))
+        .collect()
+    }
+
+    fn extract(&self, html: &Html) -> Extracted {
+        let values =
+            |rule: &Option<Rule>| rule.as_ref().map(|r| r.values(html)).unwrap_or_default();
+
+        let phones = values(&self.phones);
+        let labelled = |label: &Option<String>| {
+            let i = phones.iter().position(|p| Some(p) == label.as_ref())?;
+            phones.get(i + 1).cloned()
+        };
+        let uk_phonetic = labelled(&self.labels.uk);
+        let us_phonetic = labelled(&self.labels.us);
+
+        let phonetic = us_phonetic
+            .clone()
+            .or_else(|| uk_phonetic.clone())
+            .or_else(|| values(&self.phonetic).into_iter().next())
+            .or_else(|| phones.first().cloned())
+            .filter(|p| !p.is_empty());
+
+        let pos = values(&self.pos);
+        let explains = self
+            .explains
+            .values(html)
+            .into_iter()
+            .enumerate()
+            .map(|(i, exp)| match pos.get(i) {
+                Some(pos) => format!("{} {}", pos, exp),
+                None => exp,
+            })
+            .collect();
+
+        let web = self
+            .web_keys
+            .values(html)
+            .into_iter()
+            .zip(self.web_values.groups(html))
+            .collect();
+
+        Extracted {
+            translation: self.translation.values(html).into_iter().next(),
+            explains,
+            phonetic,
+            uk_phonetic,
+            us_phonetic,
+            web,
+        }
+    }
+}
+
+impl ScraperRules {
+    /// The rules embedded in ydcv
+    pub fn embedded() -> Arc<ScraperRules> {
+        Arc::clone(&DEFAULT_RULES)
+    }
+
+    /// Rules from `scraper_rules` in the config, or from `scraper.toml` next to
+    /// the default config file, falling back to the embedded rules
+    pub fn load(config: &Config) -> Result<Arc<ScraperRules>, Box<dyn Error>> {
+        let path = match &config.scraper_rules {
+            Some(path) => path.clone(),
+            None => match Config::default_dir().map(|d| d.join("scraper.toml")) {
+                Some(path) if path.exists() => path,
+                _ => return Ok(ScraperRules::embedded()),
+            },
+        };
+
+        let s = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let rules = toml::from_str(&s).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Arc::new(rules))
+    }
+
+    /// Whether the page says the word was not found
+    pub fn is_no_data(&self, html: &Html) -> bool {
+        html.select(&self.no_data.selector)
+            .any(|element| element.text().next().is_some())
+    }
+
+    /// Match counts of every rule of a direction, to spot selectors broken by markup changes
+    pub fn check(&self, html: &Html, chinese: bool) -> Vec<RuleCheck> {
+        if chinese {
+            self.zh2en.check(html)
+        } else {
+            self.en2zh.check(html)
+        }
+    }
+
+    pub fn en2zh(&self, html: &Html) -> Extracted {
+        self.en2zh.extract(html)
+    }
+
+    pub fn zh2en(&self, html: &Html) -> Extracted {
+        self.zh2en.extract(html)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static EN2ZH: &str = r#"
+    <div class="phone_con">
+        <div class="per-phone"><span>英</span><span class="phonetic">/ həˈləʊ /</span></div>
+        <div class="per-phone"><span>美</span><span class="phonetic">/ həˈloʊ /</span></div>
+    </div>
+    <div class="basic">
+        <ul class="word-exp"><span class="pos">int.</span><span class="trans">喂，你好</span></ul>
+        <ul class="word-exp"><span class="pos">n.</span><span class="trans">招呼</span></ul>
+    </div>
+    <div class="web_trans"><div class="col2">
+        <span class="point">Hello Kitty</span><span class="sen-phrase">凯蒂猫 ; 吉蒂猫</span>
+    </div></div>
+    "#;
+
+    #[test]
+    fn test_embedded_rules_en2zh() {
+        let extracted = ScraperRules::embedded().en2zh(&Html::parse_document(EN2ZH));
+        assert_eq!(
+            Extracted {
+                translation: Some("喂".to_owned()),
+                explains: vec!["int. 喂，你好".to_owned(), "n. 招呼".to_owned()],
+                phonetic: Some("həˈloʊ".to_owned()),
+                uk_phonetic: Some("həˈləʊ".to_owned()),
+                us_phonetic: Some("həˈloʊ".to_owned()),
+                web: vec![(
+                    "Hello Kitty".to_owned(),
+                    vec!["凯蒂猫".to_owned(), "吉蒂猫".to_owned()]
+                )],
+            },
+            extracted
+        );
+    }
+
+    #[test]
+    fn test_no_data() {
+        let rules = ScraperRules::embedded();
+        assert!(rules.is_no_data(&Html::parse_document(
+            r#"<div class="no-data-prompt">no result</div>"#
+        )));
+        assert!(!rules.is_no_data(&Html::parse_document(EN2ZH)));
+    }
+
+    #[test]
+    fn test_invalid_selector() {
+        let err = toml::from_str::<ScraperRules>(
+            &include_str!("extract.toml").replace(".no-data-prompt", "..broken"),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("selector ..broken"), "{}", err);
+    }
+}
@@ -1,4 +1,8 @@
 //! Formatters used by `YdResponse::explain`
+//!
+//! A formatter styles the semantic elements of an explanation (`Element`) and
+//! may lay out the whole entry (`Entry`) its own way; the default layout is
+//! the indented text of the terminal.
 
 #[cfg(all(feature = "notify", unix))]
 use notify_rust::Notification;
@@ -7,43 +11,367 @@ use notify_rust::Notification;
 use winrt_notification::{Duration, Toast};
 
 extern crate htmlescape;
+use crate::backend::result_url;
+use crate::dict::Definition;
+use crate::layout::{self, Piece};
+use crate::theme::Theme;
+use crate::translate::Translation;
+use crate::ydresponse::{YdResponse, YdWeb};
 use htmlescape::encode_minimal;
+use reqwest::Url;
+use serde::Serialize;
+use std::str::FromStr;
 
-macro_rules! def {
-    ($($n:ident),*) => { $(
-        fn $n (&self, s: &str) -> String;
-    )* }
+/// What a piece of text is in an explanation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Element {
+    /// the word or text looked up
+    Headword,
+    Phonetic,
+    /// translation of the headword, or of a full text
+    Translation,
+    /// part of speech starting a sense, such as `n.`
+    Pos,
+    Sense,
+    SectionHeader,
+    /// phrase of a web reference
+    WebKey,
+    /// translation of a web reference
+    WebValue,
+    /// line of a definition from a DICT server
+    Definition,
+    Error,
+}
+
+impl Element {
+    pub const ALL: [Element; 10] = [
+        Element::Headword,
+        Element::Phonetic,
+        Element::Translation,
+        Element::Pos,
+        Element::Sense,
+        Element::SectionHeader,
+        Element::WebKey,
+        Element::WebValue,
+        Element::Definition,
+        Element::Error,
+    ];
+
+    /// Name of the element in themes and CSS classes
+    pub fn name(self) -> &'static str {
+        match self {
+            Element::Headword => "headword",
+            Element::Phonetic => "phonetic",
+            Element::Translation => "translation",
+            Element::Pos => "pos",
+            Element::Sense => "sense",
+            Element::SectionHeader => "section-header",
+            Element::WebKey => "web-key",
+            Element::WebValue => "web-value",
+            Element::Definition => "definition",
+            Element::Error => "error",
+        }
+    }
+}
+
+impl FromStr for Element {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Element, String> {
+        Element::ALL
+            .into_iter()
+            .find(|e| e.name() == s)
+            .ok_or_else(|| {
+                let names = Element::ALL.map(Element::name);
+                format!(
+                    "unknown element '{}', expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Pronunciation of the headword
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum Phonetic<'a> {
+    UkUs { uk: &'a str, us: &'a str },
+    One(&'a str),
+}
+
+/// A sense, split into its part of speech and its meaning
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Sense<'a> {
+    pub pos: Option<&'a str>,
+    pub text: &'a str,
+}
+
+impl<'a> Sense<'a> {
+    /// Split `sense` after a leading part of speech such as `n.` or `vt.`
+    pub fn new(sense: &'a str) -> Sense<'a> {
+        match sense.split_once(' ') {
+            Some((pos, text))
+                if pos.len() <= 6
+                    && pos.len() > 1
+                    && pos.ends_with('.')
+                    && pos[..pos.len() - 1].bytes().all(|b| b.is_ascii_lowercase()) =>
+            {
+                Sense {
+                    pos: Some(pos),
+                    text,
+                }
+            }
+            _ => Sense {
+                pos: None,
+                text: sense,
+            },
+        }
+    }
+}
+
+/// Everything an explanation shows, in order
+#[derive(Serialize, Debug)]
+pub struct Entry<'a> {
+    pub headword: &'a str,
+    pub phonetic: Option<Phonetic<'a>>,
+    pub translation: Option<&'a [String]>,
+    pub senses: Vec<Sense<'a>>,
+    pub web: &'a [YdWeb],
+    pub definitions: &'a [Definition],
+}
+
+impl<'a> Entry<'a> {
+    /// The entry of `response`, or `None` when it holds no result
+    pub fn new(response: &'a YdResponse) -> Option<Entry<'a>> {
+        let basic = response.basic();
+        let entry = Entry {
+            headword: response.query(),
+            phonetic: basic.and_then(|b| match (b.uk_phonetic(), b.us_phonetic()) {
+                (Some(uk), Some(us)) => Some(Phonetic::UkUs { uk, us }),
+                _ => b.phonetic().map(Phonetic::One),
+            }),
+            translation: response.translation().filter(|t| !t.is_empty()),
+            senses: basic
+                .map(|b| b.explains().iter().map(|s| Sense::new(s)).collect())
+                .unwrap_or_default(),
+            web: response.web().unwrap_or_default(),
+            definitions: response.definitions().unwrap_or_default(),
+        };
+        let empty = entry.translation.is_none()
+            && entry.senses.is_empty()
+            && entry.web.is_empty()
+            && entry.definitions.is_empty();
+        (response.is_ok() && !empty).then_some(entry)
+    }
+
+    /// Title of the section of a DICT definition
+    pub fn definition_title(definition: &Definition) -> &str {
+        if definition.description.is_empty() {
+            &definition.database
+        } else {
+            &definition.description
+        }
+    }
 }
 
 /// Base trait for formatters
 pub trait Formatter {
-    def!(red);
-    def!(yellow);
-    def!(purple);
-    def!(cyan);
-    def!(underline);
-    def!(default);
+    /// Style `s`, which is an `element`
+    fn style(&self, element: Element, s: &str) -> String;
+
+    /// Lay `entry` out; by default one indented line per item, as on a terminal
+    fn explain(&self, entry: &Entry) -> String {
+        explain_lines(self, entry)
+    }
+
+    /// Link `s`, the text of an element, to the lookup of `word`; as is by
+    /// default
+    fn link(&self, s: &str, _word: &str) -> String {
+        s.to_owned()
+    }
+
+    /// Columns to wrap the default layout to, or `None` not to wrap it
+    fn width(&self) -> Option<usize> {
+        None
+    }
+
+    /// Lay a full-text `translation` out; by default one paragraph after the
+    /// other, wrapped to `width`
+    fn translation(&self, translation: &Translation) -> String {
+        explain_translation(self, translation)
+    }
+
+    /// Show `message`, telling why a lookup failed
+    fn error(&self, message: &str) -> String {
+        self.style(Element::Error, message)
+    }
+
+    /// What to show when a lookup has no result
+    fn no_result(&self) -> String {
+        self.style(Element::Error, " -- No result for this query.")
+    }
 
     fn print(&mut self, word: &str, body: &str);
 }
 
-/// Plain text formatter
-pub struct PlainFormatter;
+/// The default layout of `Formatter::explain`, wrapped to `Formatter::width`
+/// with the continuation lines under the text of their bullet
+fn explain_lines<F: Formatter + ?Sized>(fmt: &F, entry: &Entry) -> String {
+    let mut result: Vec<String> = vec![];
+    let wrap = |first: &str, indent: &str, pieces: &[Piece]| {
+        layout::wrap(first, indent, pieces, fmt.width(), |e, s| fmt.style(e, s))
+    };
+
+    if entry.phonetic.is_none() && entry.senses.is_empty() && entry.web.is_empty() {
+        result.push(style_linked(
+            fmt,
+            Element::Headword,
+            entry.headword,
+            entry.headword,
+        ));
+        if let Some(translation) = entry.translation {
+            result.push(fmt.style(Element::SectionHeader, "  Translation:"));
+            let translation = translation.join("；");
+            result.extend(wrap(
+                "    ",
+                "    ",
+                &[(Some(Element::Translation), &translation)],
+            ));
+        }
+        explain_definitions(fmt, entry, &mut result);
+        return result.join("\n");
+    }
+
+    let mut headline = vec![(Some(Element::Headword), entry.headword), (None, " ")];
+    match entry.phonetic {
+        Some(Phonetic::UkUs { uk, us }) => headline.extend([
+            (None, " UK: ["),
+            (Some(Element::Phonetic), uk),
+            (None, "], US: ["),
+            (Some(Element::Phonetic), us),
+            (None, "]"),
+        ]),
+        Some(Phonetic::One(phonetic)) => headline.extend([
+            (None, "["),
+            (Some(Element::Phonetic), phonetic),
+            (None, "]"),
+        ]),
+        None => {}
+    }
+    let translation = entry.translation.map(|t| t.join("; ")).unwrap_or_default();
+    headline.extend([(None, " "), (Some(Element::Translation), &translation)]);
+    result.extend(layout::wrap("", "  ", &headline, fmt.width(), |e, s| {
+        style_linked(fmt, e, s, entry.headword)
+    }));
+
+    if !entry.senses.is_empty() {
+        result.push(fmt.style(Element::SectionHeader, "  Word Explanation:"));
+        for sense in &entry.senses {
+            let mut pieces = vec![];
+            if let Some(pos) = sense.pos {
+                pieces.extend([(Some(Element::Pos), pos), (None, " ")]);
+            }
+            pieces.push((Some(Element::Sense), sense.text));
+            result.extend(wrap("     * ", "       ", &pieces));
+        }
+    }
+
+    if !entry.web.is_empty() {
+        result.push(fmt.style(Element::SectionHeader, "  Web Reference:"));
+        for item in entry.web {
+            result.extend(layout::wrap(
+                "     * ",
+                "       ",
+                &[(Some(Element::WebKey), item.key())],
+                fmt.width(),
+                |e, s| style_linked(fmt, e, s, item.key()),
+            ));
+            let mut values = vec![];
+            for (i, value) in item.value().iter().enumerate() {
+                if i > 0 {
+                    values.push((None, "；"));
+                }
+                values.push((Some(Element::WebValue), value.as_str()));
+            }
+            result.extend(wrap("       ", "       ", &values));
+        }
+    }
+
+    explain_definitions(fmt, entry, &mut result);
+    result.join("\n")
+}
+
+/// The default layout of `Formatter::translation`
+fn explain_translation<F: Formatter + ?Sized>(fmt: &F, translation: &Translation) -> String {
+    translation
+        .paragraphs
+        .iter()
+        .flat_map(|p| {
+            layout::wrap(
+                "",
+                "",
+                &[(Some(Element::Translation), &p.translation)],
+                fmt.width(),
+                |e, s| fmt.style(e, s),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Style `s` as `element`, linking it to the lookup of `word` if it is a
+/// headword or a web key, even when it is only a part of it on a wrapped line
+fn style_linked<F: Formatter + ?Sized>(fmt: &F, element: Element, s: &str, word: &str) -> String {
+    match element {
+        Element::Headword | Element::WebKey => fmt.link(&fmt.style(element, s), word),
+        _ => fmt.style(element, s),
+    }
+}
+
+/// Append the DICT definitions, under the description of their database
+fn explain_definitions<F: Formatter + ?Sized>(fmt: &F, entry: &Entry, result: &mut Vec<String>) {
+    for definition in entry.definitions {
+        let title = format!("  {}:", Entry::definition_title(definition));
+        result.push(fmt.style(Element::SectionHeader, &title));
+        for line in definition.text.lines() {
+            // keep the indentation of the server under that of the section
+            let text = line.trim_start();
+            let indent = "     ".to_owned() + &line[..line.len() - text.len()];
+            result.extend(layout::wrap(
+                &indent,
+                &indent,
+                &[(Some(Element::Definition), text)],
+                fmt.width(),
+                |e, s| fmt.style(e, s),
+            ));
+        }
+    }
+}
 
-macro_rules! plain {
-    ($($n:ident),*) => { $(
-        fn $n (&self, s: &str) -> String { s.to_owned() }
-    )* }
+/// Plain text formatter
+pub struct PlainFormatter {
+    width: Option<usize>,
 }
 
 impl PlainFormatter {
     pub fn new(_: bool) -> PlainFormatter {
-        PlainFormatter {}
+        PlainFormatter { width: None }
+    }
+
+    pub fn set_width(&mut self, width: Option<usize>) {
+        self.width = width;
     }
 }
 
 impl Formatter for PlainFormatter {
-    plain!(default, red, yellow, purple, cyan, underline);
+    fn style(&self, _: Element, s: &str) -> String {
+        s.to_owned()
+    }
+
+    fn width(&self) -> Option<usize> {
+        self.width
+    }
 
     fn print(&mut self, _: &str, body: &str) {
         println!("{}", body);
@@ -64,17 +392,15 @@ impl WinFormatter {
     }
 }
 
-#[cfg(all(feature = "notify", windows))]
-macro_rules! ignore {
-    ($($n:ident),*) => { $(
-        fn $n (&self, _s: &str) -> String { "".to_owned() }
-    )* }
-}
-
 #[cfg(all(feature = "notify", windows))]
 impl Formatter for WinFormatter {
-    plain!(default, red, yellow, purple, underline);
-    ignore!(cyan);
+    fn style(&self, element: Element, s: &str) -> String {
+        match element {
+            // toasts have few lines, so leave the headers out
+            Element::SectionHeader => "".to_owned(),
+            _ => s.to_owned(),
+        }
+    }
 
     fn print(&mut self, _word: &str, body: &str) {
         if self.notify {
@@ -94,27 +420,64 @@ impl Formatter for WinFormatter {
 }
 
 /// Ansi escaped colored formatter
-pub struct AnsiFormatter;
-
-macro_rules! ansi {
-    ($( $n:ident = $x:expr ),*) => { $(
-        fn $n (&self, s: &str) -> String {
-            format!("\x1b[{}m{}\x1b[0m", $x, s)
-        }
-    )* }
+pub struct AnsiFormatter {
+    theme: Theme,
+    truecolor: bool,
+    width: Option<usize>,
+    /// result page the links point to
+    hyperlinks: Option<Url>,
 }
 
 impl AnsiFormatter {
+    /// Formatter with the `dark` theme, the colors of the original ydcv
     pub fn new(_: bool) -> AnsiFormatter {
-        AnsiFormatter {}
+        AnsiFormatter::with_theme(Theme::dark())
+    }
+
+    /// Formatter with `theme`, whose truecolors are approximated unless
+    /// `$COLORTERM` tells the terminal has them
+    pub fn with_theme(theme: Theme) -> AnsiFormatter {
+        AnsiFormatter {
+            theme,
+            truecolor: crate::theme::has_truecolor(),
+            width: None,
+            hyperlinks: None,
+        }
+    }
+
+    /// Link the headword and the web keys to their result page on the
+    /// scraper backend at `base`, such as `Backend::url`, with OSC 8 escapes,
+    /// which some terminals make clickable; `None` not to link them
+    pub fn set_hyperlinks(&mut self, base: Option<Url>) {
+        self.hyperlinks = base;
+    }
+
+    pub fn set_width(&mut self, width: Option<usize>) {
+        self.width = width;
     }
 }
 
 impl Formatter for AnsiFormatter {
-    ansi!(red = 31, yellow = 33, purple = 35, cyan = 36, underline = 4);
+    fn style(&self, element: Element, s: &str) -> String {
+        match self.theme.get(element).sgr(self.truecolor) {
+            Some(sgr) => format!("\x1b[{}m{}\x1b[0m", sgr, s),
+            None => s.to_owned(),
+        }
+    }
 
-    fn default(&self, s: &str) -> String {
-        s.to_owned()
+    fn link(&self, s: &str, word: &str) -> String {
+        match &self.hyperlinks {
+            Some(base) => format!(
+                "\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\",
+                result_url(base, word),
+                s
+            ),
+            None => s.to_owned(),
+        }
+    }
+
+    fn width(&self) -> Option<usize> {
+        self.width
     }
 
     fn print(&mut self, _: &str, body: &str) {
@@ -154,26 +517,17 @@ impl HtmlFormatter {
     }
 }
 
-macro_rules! html {
-    ($( $n:ident = $x:expr ),*) => { $(
-        fn $n (&self, s: &str) -> String {
-            format!(r#"<span color="{}">{}</span>"#, $x, encode_minimal(s))
-        }
-    )* }
-}
-
 impl Formatter for HtmlFormatter {
-    html!(
-        red = "red",
-        yellow = "goldenrod",
-        purple = "purple",
-        cyan = "navy"
-    );
-    fn underline(&self, s: &str) -> String {
-        format!(r#"<u>{}</u>"#, encode_minimal(s))
-    }
-    fn default(&self, s: &str) -> String {
-        encode_minimal(s)
+    fn style(&self, element: Element, s: &str) -> String {
+        let color = match element {
+            Element::Error => "red",
+            Element::Phonetic | Element::WebKey => "goldenrod",
+            Element::WebValue => "purple",
+            Element::SectionHeader => "navy",
+            Element::Headword => return format!(r#"<u>{}</u>"#, encode_minimal(s)),
+            _ => return encode_minimal(s),
+        };
+        format!(r#"<span color="{}">{}</span>"#, color, encode_minimal(s))
     }
 
     #[cfg(all(feature = "notify", unix))]
@@ -197,11 +551,315 @@ impl Formatter for HtmlFormatter {
     }
 }
 
+/// Stylesheet embedded in articles, for the classes `ArticleFormatter` uses
+pub const ARTICLE_CSS: &str = "\
+.ydcv-article { font-family: sans-serif; }
+.ydcv-headword { font-weight: bold; font-size: 120%; }
+.ydcv-phonetic { color: darkgoldenrod; margin-left: 0.5em; }
+.ydcv-translation { margin-left: 0.5em; }
+.ydcv-pos { font-style: italic; color: gray; }
+.ydcv-section h4 { color: navy; margin: 0.6em 0 0.2em; }
+.ydcv-section ul { margin: 0; padding-left: 1.5em; }
+.ydcv-web dt { color: darkgoldenrod; }
+.ydcv-web dd { color: purple; margin-left: 1.5em; }
+.ydcv-dict pre { white-space: pre-wrap; margin: 0 0 0 1.5em; }
+.ydcv-error { color: red; }
+";
+
+/// Formatter of self-contained HTML articles, for GoldenDict and other
+/// programs embedding the result in a page
+///
+/// Unlike `HtmlFormatter`, whose Pango markup suits notifications, every
+/// section is a `<div>` with a `ydcv-*` class that a stylesheet can target.
+/// There is no article at all without a result.
+pub struct ArticleFormatter {
+    stylesheet: bool,
+}
+
+impl ArticleFormatter {
+    /// With `stylesheet`, articles start with a `<style>` of `ARTICLE_CSS`
+    pub fn new(stylesheet: bool) -> ArticleFormatter {
+        ArticleFormatter { stylesheet }
+    }
+
+    /// The article for `response`, or `None` when it holds no result
+    pub fn article(&self, response: &YdResponse) -> Option<String> {
+        Entry::new(response).map(|entry| self.explain(&entry))
+    }
+
+    /// `<span>` of the class of `element`
+    fn span(&self, element: Element, s: &str) -> String {
+        format!(
+            r#"<span class="ydcv-{}">{}</span>"#,
+            element.name(),
+            encode_minimal(s)
+        )
+    }
+}
+
+impl Formatter for ArticleFormatter {
+    fn style(&self, element: Element, s: &str) -> String {
+        self.span(element, s)
+    }
+
+    fn explain(&self, entry: &Entry) -> String {
+        let mut html = String::new();
+        if self.stylesheet {
+            html += &format!("<style>\n{}</style>\n", ARTICLE_CSS);
+        }
+        html += "<div class=\"ydcv-article\">\n<div class=\"ydcv-header\">";
+        html += &self.span(Element::Headword, entry.headword);
+        let phonetic = match entry.phonetic {
+            Some(Phonetic::UkUs { uk, us }) => Some(format!("UK: [{}], US: [{}]", uk, us)),
+            Some(Phonetic::One(phonetic)) => Some(format!("[{}]", phonetic)),
+            None => None,
+        };
+        if let Some(phonetic) = phonetic {
+            html += &self.span(Element::Phonetic, &phonetic);
+        }
+        if let Some(translation) = entry.translation {
+            html += &self.span(Element::Translation, &translation.join("; "));
+        }
+        html += "</div>\n";
+
+        if !entry.senses.is_empty() {
+            html += "<div class=\"ydcv-section ydcv-explains\"><h4>Word Explanation</h4><ul>";
+            for sense in &entry.senses {
+                html += "<li>";
+                if let Some(pos) = sense.pos {
+                    html += &self.span(Element::Pos, pos);
+                    html += " ";
+                }
+                html += &encode_minimal(sense.text);
+                html += "</li>";
+            }
+            html += "</ul></div>\n";
+        }
+        if !entry.web.is_empty() {
+            html += "<div class=\"ydcv-section ydcv-web\"><h4>Web Reference</h4><dl>";
+            for item in entry.web {
+                html += &format!(
+                    "<dt>{}</dt><dd>{}</dd>",
+                    encode_minimal(item.key()),
+                    encode_minimal(&item.value().join("；"))
+                );
+            }
+            html += "</dl></div>\n";
+        }
+        for definition in entry.definitions {
+            html += &format!(
+                "<div class=\"ydcv-section ydcv-dict\"><h4>{}</h4><pre>{}</pre></div>\n",
+                encode_minimal(Entry::definition_title(definition)),
+                encode_minimal(&definition.text)
+            );
+        }
+        html += "</div>";
+        html
+    }
+
+    fn no_result(&self) -> String {
+        "".to_owned()
+    }
+
+    fn print(&mut self, _: &str, body: &str) {
+        if !body.is_empty() {
+            println!("{}", body);
+        }
+    }
+}
+
+/// Markdown formatter, for wiki pages, issue trackers and chats
+pub struct MarkdownFormatter;
+
+/// Escape characters that Markdown would take as markup, and the list and
+/// heading markers that only count at the start of a line
+fn escape_markdown(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for line in s.split_inclusive('\n') {
+        let text = line.trim_start();
+        escaped.push_str(&line[..line.len() - text.len()]);
+        // "1." and "1)" start ordered lists, "-", "+" and "=" lists and headings
+        let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let marker = match text[digits..].chars().next() {
+            Some('.' | ')') if digits > 0 => Some(digits),
+            Some('-' | '+' | '=') if digits == 0 => Some(0),
+            _ => None,
+        };
+        for (i, c) in text.char_indices() {
+            if "\\`*_[]<>#|~!&".contains(c) || marker == Some(i) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// `s` as a code span, with enough backticks around it
+fn code_span(s: &str) -> String {
+    if s.contains('`') {
+        let fence = "`".repeat(longest_run(s, '`') + 1);
+        format!("{} {} {}", fence, s, fence)
+    } else {
+        format!("`{}`", s)
+    }
+}
+
+/// Length of the longest run of `c` in `s`
+fn longest_run(s: &str, c: char) -> usize {
+    s.split(|x| x != c).map(str::len).max().unwrap_or(0)
+}
+
+impl MarkdownFormatter {
+    pub fn new() -> MarkdownFormatter {
+        MarkdownFormatter {}
+    }
+}
+
+impl Default for MarkdownFormatter {
+    fn default() -> MarkdownFormatter {
+        MarkdownFormatter::new()
+    }
+}
+
+impl Formatter for MarkdownFormatter {
+    fn style(&self, element: Element, s: &str) -> String {
+        match element {
+            Element::Headword | Element::SectionHeader => format!("**{}**", escape_markdown(s)),
+            Element::Phonetic => code_span(s),
+            Element::Pos | Element::Error => format!("*{}*", escape_markdown(s)),
+            // definitions are laid out by their authors, in code blocks
+            Element::Definition => s.to_owned(),
+            _ => escape_markdown(s),
+        }
+    }
+
+    /// The headword as a heading, then its phonetic and translation, and a
+    /// list per section
+    fn explain(&self, entry: &Entry) -> String {
+        let mut blocks = vec![format!("## {}", escape_markdown(entry.headword))];
+
+        let mut summary = vec![];
+        match entry.phonetic {
+            Some(Phonetic::UkUs { uk, us }) => {
+                summary.push(format!("UK: {} US: {}", code_span(uk), code_span(us)))
+            }
+            Some(Phonetic::One(phonetic)) => summary.push(code_span(phonetic)),
+            None => {}
+        }
+        if let Some(translation) = entry.translation {
+            summary.push(escape_markdown(&translation.join("; ")));
+        }
+        if !summary.is_empty() {
+            blocks.push(summary.join(" "));
+        }
+
+        if !entry.senses.is_empty() {
+            blocks.push("### Word Explanation".to_owned());
+            blocks.push(
+                entry
+                    .senses
+                    .iter()
+                    .map(|sense| match sense.pos {
+                        Some(pos) => format!(
+                            "- {} {}",
+                            self.style(Element::Pos, pos),
+                            escape_markdown(sense.text)
+                        ),
+                        None => format!("- {}", escape_markdown(sense.text)),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+        }
+        if !entry.web.is_empty() {
+            blocks.push("### Web Reference".to_owned());
+            blocks.push(
+                entry
+                    .web
+                    .iter()
+                    .map(|item| {
+                        format!(
+                            "- **{}**: {}",
+                            escape_markdown(item.key()),
+                            escape_markdown(&item.value().join("；"))
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+        }
+        for definition in entry.definitions {
+            let fence = "`".repeat(3.max(longest_run(&definition.text, '`') + 1));
+            blocks.push(format!(
+                "### {}",
+                escape_markdown(Entry::definition_title(definition))
+            ));
+            blocks.push(format!("{}text\n{}\n{}", fence, definition.text, fence));
+        }
+        blocks.join("\n\n")
+    }
+
+    fn no_result(&self) -> String {
+        self.style(Element::Error, "No result for this query.")
+    }
+
+    fn print(&mut self, _: &str, body: &str) {
+        println!("{}", body);
+    }
+}
+
+/// JSON formatter: the semantic structure of the entry, unlike the raw reply
+/// of the server
+pub struct JsonFormatter;
+
+impl JsonFormatter {
+    pub fn new() -> JsonFormatter {
+        JsonFormatter {}
+    }
+}
+
+impl Default for JsonFormatter {
+    fn default() -> JsonFormatter {
+        JsonFormatter::new()
+    }
+}
+
+impl Formatter for JsonFormatter {
+    fn style(&self, _: Element, s: &str) -> String {
+        s.to_owned()
+    }
+
+    fn explain(&self, entry: &Entry) -> String {
+        serde_json::to_string(entry).unwrap()
+    }
+
+    fn translation(&self, translation: &Translation) -> String {
+        serde_json::to_string(translation).unwrap()
+    }
+
+    fn error(&self, message: &str) -> String {
+        serde_json::json!({ "error": message }).to_string()
+    }
+
+    fn no_result(&self) -> String {
+        "null".to_owned()
+    }
+
+    fn print(&mut self, _: &str, body: &str) {
+        println!("{}", body);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::formatters::{AnsiFormatter, HtmlFormatter, PlainFormatter};
+    use crate::formatters::{
+        code_span, escape_markdown, AnsiFormatter, ArticleFormatter, Formatter, HtmlFormatter,
+        JsonFormatter, MarkdownFormatter, PlainFormatter, Sense,
+    };
+    use crate::translate::{Paragraph, Translation};
     use crate::ydclient::*;
-    use reqwest::blocking::Client;
+    use reqwest::{Client, Url};
 
     static RAW_FELIX: &str = r#"
     {
@@ -273,6 +931,71 @@ Felix ['fi:liks] 费利克斯
         );
     }
 
+    #[test]
+    fn test_explain_hyperlinks() {
+        let mut fmt = AnsiFormatter::new(false);
+        fmt.set_hyperlinks(Some(Url::parse("https://www.youdao.com/result").unwrap()));
+        let result = Client::new()
+            .decode_result(RAW_FELIX)
+            .unwrap()
+            .explain(&fmt);
+        let lines: Vec<_> = result.lines().collect();
+        assert!(
+            lines[0].starts_with(
+                "\x1b]8;;https://www.youdao.com/result?word=Felix&lang=en\x1b\\\x1b[4mFelix\x1b[0m\x1b]8;;\x1b\\ "
+            ),
+            "{:?}",
+            lines[0]
+        );
+        assert_eq!(
+            "     * \x1b]8;;https://www.youdao.com/result?word=Felix+Magath&lang=en\x1b\\\x1b[33mFelix Magath\x1b[0m\x1b]8;;\x1b\\",
+            lines[6]
+        );
+
+        // both lines of a wrapped web key link to the whole key
+        fmt.set_width(Some(14));
+        let result = Client::new()
+            .decode_result(RAW_FELIX)
+            .unwrap()
+            .explain(&fmt);
+        let link = "\x1b]8;;https://www.youdao.com/result?word=Felix+Magath&lang=en\x1b\\";
+        assert!(
+            result.contains(&format!(
+                "     * {}\x1b[33mFelix\x1b[0m\x1b]8;;\x1b\\\n       {}\x1b[33mMagath\x1b[0m",
+                link, link
+            )),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_explain_wrapped() {
+        let mut fmt = PlainFormatter::new(false);
+        fmt.set_width(Some(28));
+        assert_eq!(
+            "Felix ['fi:liks] 费利克斯
+  Word Explanation:
+     * n. 菲力克斯（男子
+       名）；费力克斯制导炸
+       弹
+  Web Reference:
+     * Felix
+       费利克斯；费利斯；菲
+       力克斯
+     * Felix Magath
+       菲利克斯·马加特；马加
+       特；菲利斯·马加夫
+     * Felix Bloch
+       费利克斯·布洛赫；布洛
+       赫；傅里克",
+            Client::new()
+                .decode_result(RAW_FELIX)
+                .unwrap()
+                .explain(&fmt)
+        );
+    }
+
     #[test]
     fn test_explain_html_0() {
         assert_eq!(
@@ -298,6 +1021,152 @@ Felix ['fi:liks] 费利克斯
         );
     }
 
+    #[test]
+    fn test_explain_markdown() {
+        assert_eq!(
+            "## Felix
+
+`'fi:liks` 费利克斯
+
+### Word Explanation
+
+- *n.* 菲力克斯（男子名）；费力克斯制导炸弹
+
+### Web Reference
+
+- **Felix**: 费利克斯；费利斯；菲力克斯
+- **Felix Magath**: 菲利克斯·马加特；马加特；菲利斯·马加夫
+- **Felix Bloch**: 费利克斯·布洛赫；布洛赫；傅里克",
+            Client::new()
+                .decode_result(RAW_FELIX)
+                .unwrap()
+                .explain(&MarkdownFormatter::new())
+        );
+
+        let escaped = Client::new()
+            .decode_result(
+                r#"{"query":"*_a_*","errorCode":0,"translation":["[b](c) <d> #e"],
+                    "basic":{"phonetic":"x`y","explains":[]}}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            r"## \*\_a\_\*
+
+`` x`y `` \[b\](c) \<d\> \#e",
+            escaped.explain(&MarkdownFormatter::new())
+        );
+        assert_eq!("``` a``b ```", code_span("a``b"));
+
+        assert_eq!(r"\- informal", escape_markdown("- informal"));
+        assert_eq!(r"\+1", escape_markdown("+1"));
+        assert_eq!(r"1\. a greeting", escape_markdown("1. a greeting"));
+        assert_eq!(r"12\) hi", escape_markdown("12) hi"));
+        assert_eq!("a\n  \\- b\n\\=", escape_markdown("a\n  - b\n="));
+        assert_eq!(r"\&amp; \#1 a-b 1.5", escape_markdown("&amp; #1 a-b 1.5"));
+    }
+
+    #[test]
+    fn test_explain_json() {
+        let entry: serde_json::Value = serde_json::from_str(
+            &Client::new()
+                .decode_result(RAW_FELIX)
+                .unwrap()
+                .explain(&JsonFormatter::new()),
+        )
+        .unwrap();
+        assert_eq!("Felix", entry["headword"]);
+        assert_eq!("'fi:liks", entry["phonetic"]);
+        assert_eq!(
+            serde_json::json!([{"pos": "n.", "text": "菲力克斯（男子名）；费力克斯制导炸弹"}]),
+            entry["senses"]
+        );
+        assert_eq!("Felix Magath", entry["web"][1]["key"]);
+
+        let empty = Client::new()
+            .decode_result(r#"{"query":"xyzzy","errorCode":0}"#)
+            .unwrap();
+        assert_eq!("null", empty.explain(&JsonFormatter::new()));
+        assert_eq!(
+            r#"{"error":"Error looking-up word x: \"timeout\""}"#,
+            JsonFormatter::new().error(r#"Error looking-up word x: "timeout""#)
+        );
+        assert_eq!(
+            " -- No result for this query.",
+            empty.explain(&PlainFormatter::new(false))
+        );
+    }
+
+    #[test]
+    fn test_translation_json() {
+        let translation = Translation {
+            paragraphs: vec![
+                Paragraph {
+                    source: "Hello world.".to_owned(),
+                    translation: "你好，世界。".to_owned(),
+                },
+                Paragraph {
+                    source: "Bye.".to_owned(),
+                    translation: "再见。".to_owned(),
+                },
+            ],
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&translation.explain(&JsonFormatter::new())).unwrap();
+        assert_eq!(
+            serde_json::json!({"paragraphs": [
+                {"source": "Hello world.", "translation": "你好，世界。"},
+                {"source": "Bye.", "translation": "再见。"}
+            ]}),
+            json
+        );
+        assert_eq!(
+            "你好，世界。\n再见。",
+            translation.explain(&PlainFormatter::new(false))
+        );
+    }
+
+    #[test]
+    fn test_sense() {
+        assert_eq!(
+            Sense {
+                pos: Some("vt."),
+                text: "接受；承认"
+            },
+            Sense::new("vt. 接受；承认")
+        );
+        assert_eq!(None, Sense::new("for the time being").pos);
+        assert_eq!(None, Sense::new("U.S. 美国").pos);
+        assert_eq!(None, Sense::new("n.").pos);
+    }
+
+    #[test]
+    fn test_article() {
+        let article = ArticleFormatter::new(false)
+            .article(&Client::new().decode_result(RAW_FELIX).unwrap())
+            .unwrap();
+        assert!(article.starts_with(concat!(
+            "<div class=\"ydcv-article\">\n<div class=\"ydcv-header\">",
+            "<span class=\"ydcv-headword\">Felix</span>",
+            "<span class=\"ydcv-phonetic\">[&#x27;fi:liks]</span>",
+            "<span class=\"ydcv-translation\">费利克斯</span></div>\n",
+            "<div class=\"ydcv-section ydcv-explains\"><h4>Word Explanation</h4>",
+            "<ul><li><span class=\"ydcv-pos\">n.</span> 菲力克斯（男子名）；费力克斯制导炸弹</li></ul></div>\n",
+            "<div class=\"ydcv-section ydcv-web\"><h4>Web Reference</h4>",
+            "<dl><dt>Felix</dt><dd>费利克斯；费利斯；菲力克斯</dd>"
+        )));
+        assert!(article.ends_with("</dl></div>\n</div>"), "{}", article);
+
+        let styled = ArticleFormatter::new(true)
+            .article(&Client::new().decode_result(RAW_FELIX).unwrap())
+            .unwrap();
+        assert!(styled.starts_with("<style>\n.ydcv-article"));
+
+        let empty = Client::new()
+            .decode_result(r#"{"query":"xyzzy","errorCode":0}"#)
+            .unwrap();
+        assert_eq!(None, ArticleFormatter::new(true).article(&empty));
+    }
+
     #[test]
     fn test_explain_html_1() {
         let result = format!(
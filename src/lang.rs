@@ -1,48 +1,386 @@
-pub fn is_chinese(s: &str) -> bool {
+//! language codes and detection helpers
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Languages understood by the openapi, identified by their openapi codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lang {
+    Auto,
+    ZhChs,
+    ZhCht,
+    En,
+    Ja,
+    Ko,
+    Fr,
+    De,
+    Es,
+    Ru,
+    Pt,
+    It,
+    Vi,
+    Id,
+    Ar,
+    Nl,
+    Th,
+}
+
+impl Lang {
+    /// All languages accepted by `--from/--to`
+    pub const ALL: &'static [Lang] = &[
+        Lang::Auto,
+        Lang::ZhChs,
+        Lang::ZhCht,
+        Lang::En,
+        Lang::Ja,
+        Lang::Ko,
+        Lang::Fr,
+        Lang::De,
+        Lang::Es,
+        Lang::Ru,
+        Lang::Pt,
+        Lang::It,
+        Lang::Vi,
+        Lang::Id,
+        Lang::Ar,
+        Lang::Nl,
+        Lang::Th,
+    ];
+
+    /// Language code as used by the openapi `from`/`to` parameters
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::Auto => "auto",
+            Lang::ZhChs => "zh-CHS",
+            Lang::ZhCht => "zh-CHT",
+            Lang::En => "en",
+            Lang::Ja => "ja",
+            Lang::Ko => "ko",
+            Lang::Fr => "fr",
+            Lang::De => "de",
+            Lang::Es => "es",
+            Lang::Ru => "ru",
+            Lang::Pt => "pt",
+            Lang::It => "it",
+            Lang::Vi => "vi",
+            Lang::Id => "id",
+            Lang::Ar => "ar",
+            Lang::Nl => "nl",
+            Lang::Th => "th",
+        }
+    }
+
+    /// Whether the youdao.com result page can look up words from `from` to `to`
+    ///
+    /// The web page only serves the English <-> Chinese dictionary.
+    pub fn scraper_supports(from: Lang, to: Lang) -> bool {
+        let supported = |l| matches!(l, Lang::Auto | Lang::En | Lang::ZhChs | Lang::ZhCht);
+        supported(from) && supported(to) && (from == Lang::Auto || from != to)
+    }
+}
+
+impl fmt::Display for Lang {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Lang, String> {
+        Lang::ALL
+            .iter()
+            .find(|l| l.code().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "unknown language '{}', expected one of: {}",
+                    s,
+                    Lang::ALL
+                        .iter()
+                        .map(|l| l.code())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+/// Writing system guessed from the input text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Script {
+    SimplifiedChinese,
+    TraditionalChinese,
+    Japanese,
+    Korean,
+    Cyrillic,
+    Latin,
+    /// No single script dominates, e.g. `Tokyo東京`
+    Mixed,
+    /// No letters at all, e.g. only digits or punctuation
+    Unknown,
+}
+
+/// Result of `detect`: the guessed script and how sure we are, in `0.0..=1.0`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub script: Script,
+    pub confidence: f32,
+}
+
+/// Share of letters the dominant script needs before the input stops being `Mixed`
+const DOMINANT_SHARE: f32 = 0.75;
+
+/// Detections below this confidence are not used to pick the source language
+const CONFIDENT: f32 = 0.5;
+
+impl Detection {
+    pub fn is_chinese(&self) -> bool {
+        matches!(
+            self.script,
+            Script::SimplifiedChinese | Script::TraditionalChinese
+        )
+    }
+
+    /// The openapi language this detection most likely corresponds to
+    ///
+    /// Cyrillic and Latin are written in many languages, so they are left to
+    /// the server as `Lang::Auto`, like `Mixed` and `Unknown`; a wrong
+    /// explicit guess would be worse than none.
+    pub fn lang(&self) -> Lang {
+        match self.script {
+            Script::SimplifiedChinese => Lang::ZhChs,
+            Script::TraditionalChinese => Lang::ZhCht,
+            Script::Japanese => Lang::Ja,
+            Script::Korean => Lang::Ko,
+            Script::Cyrillic | Script::Latin | Script::Mixed | Script::Unknown => Lang::Auto,
+        }
+    }
+}
+
+/// Guess the script of `s` by counting the letters of each writing system
+///
+/// Punctuation, digits and whitespace are ignored. Han characters next to kana
+/// count as Japanese, and simplified and traditional Chinese are told apart by
+/// characters that only exist in one of the two forms.
+pub fn detect(s: &str) -> Detection {
+    let (mut han, mut kana, mut hangul, mut cyrillic, mut latin) = (0, 0, 0, 0, 0);
+    let (mut simplified, mut traditional) = (0, 0);
+
     for ch in s.chars() {
-        if is_chinese_char(ch) {
-            return true;
+        if is_han(ch) {
+            han += 1;
+            if SIMPLIFIED_ONLY.contains(ch) {
+                simplified += 1;
+            } else if TRADITIONAL_ONLY.contains(ch) {
+                traditional += 1;
+            }
+        } else if is_kana(ch) {
+            kana += 1;
+        } else if is_hangul(ch) {
+            hangul += 1;
+        } else if is_cyrillic(ch) {
+            cyrillic += 1;
+        } else if is_latin(ch) {
+            latin += 1;
         }
     }
 
-    false
+    let total = han + kana + hangul + cyrillic + latin;
+    if total == 0 {
+        return Detection {
+            script: Script::Unknown,
+            confidence: 0.0,
+        };
+    }
+
+    // kanji are part of Japanese text as soon as any kana shows up
+    let chinese = if kana > 0 { 0 } else { han };
+    let japanese = if kana > 0 { kana + han } else { 0 };
+
+    let (script, count) = [
+        (Script::SimplifiedChinese, chinese),
+        (Script::Japanese, japanese),
+        (Script::Korean, hangul),
+        (Script::Cyrillic, cyrillic),
+        (Script::Latin, latin),
+    ]
+    .into_iter()
+    .max_by_key(|&(_, count)| count)
+    .unwrap();
+
+    let share = count as f32 / total as f32;
+    if share < DOMINANT_SHARE {
+        return Detection {
+            script: Script::Mixed,
+            confidence: (2.0 * (1.0 - share)).min(1.0),
+        };
+    }
+
+    if script == Script::SimplifiedChinese {
+        // without any variant-specific character, either form is as likely
+        let markers = simplified + traditional;
+        let variant = if markers == 0 {
+            0.5
+        } else {
+            simplified.max(traditional) as f32 / markers as f32
+        };
+        return Detection {
+            script: if traditional > simplified {
+                Script::TraditionalChinese
+            } else {
+                Script::SimplifiedChinese
+            },
+            confidence: share * (0.5 + 0.5 * variant),
+        };
+    }
+
+    Detection {
+        script,
+        confidence: share,
+    }
+}
+
+/// Resolve `Lang::Auto` in a lookup direction using `detect`
+///
+/// The source language is only filled in when the detection is confident;
+/// the target defaults to English for Chinese input and Chinese otherwise.
+pub fn resolve(word: &str, from: Lang, to: Lang) -> (Lang, Lang) {
+    let detection = detect(word);
+
+    let from = match from {
+        Lang::Auto if detection.confidence >= CONFIDENT => detection.lang(),
+        from => from,
+    };
+    let to = match to {
+        Lang::Auto if matches!(from, Lang::ZhChs | Lang::ZhCht) || detection.is_chinese() => {
+            Lang::En
+        }
+        Lang::Auto => Lang::ZhChs,
+        to => to,
+    };
+
+    (from, to)
+}
+
+/// Common characters that only exist in simplified Chinese
+///
+/// Simplified forms that are also traditional characters of their own, such
+/// as 后 (queen), 里 (village) or 么, are left out.
+const SIMPLIFIED_ONLY: &str = "们个这来时国说对会学经发问见现还长样点开关体与机东车书语话电当应过让气从义两实进种业无间亲头万边声专区为给认识听读写买卖钱门风马鸟鱼龙飞热爱觉视观记华层际变";
+
+/// Traditional counterparts of `SIMPLIFIED_ONLY`, along with 後, 裡 and 麼
+const TRADITIONAL_ONLY: &str = "們個這來時國說對會學經發問見現還後長樣點開關體與機東車書語話電麼當應過讓氣從義兩實進種業無間裡親頭萬邊聲專區為給認識聽讀寫買賣錢門風馬鳥魚龍飛熱愛覺視觀記華層際變";
+
+#[inline]
+fn is_han(ch: char) -> bool {
+    matches!(ch as u32,
+        0x4e00..=0x9fff
+        | 0x3400..=0x4dbf   // CJK Unified Ideographs Extension A
+        | 0x20000..=0x2a6df // CJK Unified Ideographs Extension B
+        | 0x2a700..=0x2b73f // CJK Unified Ideographs Extension C
+        | 0x2b740..=0x2b81f // CJK Unified Ideographs Extension D
+        | 0x2b820..=0x2ceaf // CJK Unified Ideographs Extension E
+        | 0x3300..=0x33ff   // https://en.wikipedia.org/wiki/CJK_Compatibility
+        | 0xfe30..=0xfe4f   // https://en.wikipedia.org/wiki/CJK_Compatibility_Forms
+        | 0xf900..=0xfaff   // https://en.wikipedia.org/wiki/CJK_Compatibility_Ideographs
+        | 0x2f800..=0x2fa1f // https://en.wikipedia.org/wiki/CJK_Compatibility_Ideographs_Supplement
+    )
 }
 
 #[inline]
-fn is_chinese_char(ch: char) -> bool {
-    match ch as u32 {
-        0x4e00..=0x9fff => true,
-        0xff0c => true,            //，
-        0x3002 => true,            //。
-        0x3400..=0x4dbf => true,   // CJK Unified Ideographs Extension A
-        0x20000..=0x2a6df => true, // CJK Unified Ideographs Extension B
-        0x2a700..=0x2b73f => true, // CJK Unified Ideographs Extension C
-        0x2b740..=0x2b81f => true, // CJK Unified Ideographs Extension D
-        0x2b820..=0x2ceaf => true, // CJK Unified Ideographs Extension E
-        0x3300..=0x33ff => true,   // https://en.wikipedia.org/wiki/CJK_Compatibility
-        0xfe30..=0xfe4f => true,   // https://en.wikipedia.org/wiki/CJK_Compatibility_Forms
-        0xf900..=0xfaff => true,   // https://en.wikipedia.org/wiki/CJK_Compatibility_Ideographs
-        0x2f800..=0x2fa1f => true, // https://en.wikipedia.org/wiki/CJK_Compatibility_Ideographs_Supplement
-        0x00b7 => true,            //·
-        0x00d7 => true,            //×
-        0x2014 => true,            //—
-        0x2018 => true,            //‘
-        0x2019 => true,            //’
-        0x201c => true,            //“
-        0x201d => true,            //”
-        0x2026 => true,            //…
-        0x3001 => true,            //、
-        0x300a => true,            //《
-        0x300b => true,            //》
-        0x300e => true,            //『
-        0x300f => true,            //』
-        0x3010 => true,            //【
-        0x3011 => true,            //】
-        0xff01 => true,            //！
-        0xff08 => true,            //（
-        0xff09 => true,            //）
-        0xff1a => true,            //：
-        0xff1f => true,            //？
-        _ => false,
+fn is_kana(ch: char) -> bool {
+    matches!(ch as u32,
+        0x3041..=0x309f     // Hiragana
+        | 0x30a1..=0x30fa   // Katakana, without the middle dot and prolonged sound mark
+        | 0x31f0..=0x31ff   // Katakana Phonetic Extensions
+        | 0xff66..=0xff9d   // Halfwidth Katakana
+    )
+}
+
+#[inline]
+fn is_hangul(ch: char) -> bool {
+    matches!(ch as u32,
+        0xac00..=0xd7af     // Hangul Syllables
+        | 0x1100..=0x11ff   // Hangul Jamo
+        | 0x3130..=0x318f   // Hangul Compatibility Jamo
+    )
+}
+
+#[inline]
+fn is_cyrillic(ch: char) -> bool {
+    matches!(ch as u32, 0x0400..=0x052f)
+}
+
+#[inline]
+fn is_latin(ch: char) -> bool {
+    ch.is_ascii_alphabetic()
+        || matches!(ch as u32, 0x00c0..=0x024f) && ch != '\u{00d7}' && ch != '\u{00f7}'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_scripts() {
+        assert_eq!(Script::Latin, detect("hello").script);
+        assert_eq!(Script::Latin, detect("Überraschung!").script);
+        assert_eq!(Script::SimplifiedChinese, detect("这个问题").script);
+        assert_eq!(Script::TraditionalChinese, detect("這個問題").script);
+        assert_eq!(Script::TraditionalChinese, detect("皇后住在那裡").script);
+        assert_eq!(Script::Japanese, detect("東京へ行きます").script);
+        assert_eq!(Script::Japanese, detect("カタカナ").script);
+        assert_eq!(Script::Korean, detect("안녕하세요").script);
+        assert_eq!(Script::Cyrillic, detect("привет").script);
+        assert_eq!(Script::Mixed, detect("Tokyo東京").script);
+        assert_eq!(Script::Unknown, detect("<+*>?_").script);
+    }
+
+    #[test]
+    fn test_detect_confidence() {
+        assert_eq!(1.0, detect("hello").confidence);
+        // no variant-specific character: Chinese, but unsure which form
+        assert_eq!(0.75, detect("暂").confidence);
+        // 后 is traditional too, so nothing here hints at simplified Chinese
+        assert_eq!(
+            Detection {
+                script: Script::TraditionalChinese,
+                confidence: 1.0
+            },
+            detect("皇后後來住在宮裡")
+        );
+        assert_eq!(0.0, detect("42").confidence);
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            (Lang::Auto, Lang::ZhChs),
+            resolve("hello", Lang::Auto, Lang::Auto)
+        );
+        // French, German or Ukrainian must not be sent as English or Russian
+        assert_eq!(
+            (Lang::Auto, Lang::ZhChs),
+            resolve("Überraschung", Lang::Auto, Lang::Auto)
+        );
+        assert_eq!(
+            (Lang::Auto, Lang::ZhChs),
+            resolve("привіт", Lang::Auto, Lang::Auto)
+        );
+        assert_eq!(
+            (Lang::ZhChs, Lang::En),
+            resolve("暂时", Lang::Auto, Lang::Auto)
+        );
+        assert_eq!(
+            (Lang::Ja, Lang::ZhChs),
+            resolve("ありがとう", Lang::Auto, Lang::Auto)
+        );
+        assert_eq!(
+            (Lang::Auto, Lang::ZhChs),
+            resolve("Tokyo東京", Lang::Auto, Lang::Auto)
+        );
+        assert_eq!((Lang::Fr, Lang::De), resolve("bonjour", Lang::Fr, Lang::De));
     }
 }
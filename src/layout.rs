@@ -0,0 +1,233 @@
+//! text layout by display width
+//!
+//! Terminals show most CJK characters in two columns, so lines are measured
+//! in columns rather than in chars. Lines break at spaces and around CJK
+//! characters, but not before closing punctuation such as `；` nor after
+//! opening punctuation such as `（`.
+
+use crate::formatters::Element;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Text shown as an element, or as is for `None`
+pub type Piece<'a> = (Option<Element>, &'a str);
+
+/// Punctuation that does not start a line
+const CLOSING: &str = "，。、；：！？）」』》】〕…,.;:!?)]}";
+/// Punctuation that does not end a line
+const OPENING: &str = "（「『《【〔([{";
+
+/// Columns taken by `s` on a terminal
+pub fn display_width(s: &str) -> usize {
+    UnicodeWidthStr::width(s)
+}
+
+fn is_wide(c: char) -> bool {
+    c.width().unwrap_or(0) > 1
+}
+
+/// Whether a line may break between `before` and `after`, two non-spaces
+fn can_break(before: char, after: char) -> bool {
+    (is_wide(before) || is_wide(after)) && !CLOSING.contains(after) && !OPENING.contains(before)
+}
+
+/// Text kept on one line: a word, a CJK character or a run of spaces
+#[derive(Default)]
+struct Unit {
+    pieces: Vec<(Option<Element>, String)>,
+    width: usize,
+    space: bool,
+}
+
+impl Unit {
+    fn push(&mut self, element: Option<Element>, c: char) {
+        match self.pieces.last_mut() {
+            Some((e, s)) if *e == element => s.push(c),
+            _ => self.pieces.push((element, c.to_string())),
+        }
+        self.width += c.width().unwrap_or(0);
+    }
+
+    fn len(&self) -> usize {
+        self.pieces.iter().map(|(_, s)| s.chars().count()).sum()
+    }
+
+    /// One unit per char, to split a word longer than a line
+    fn chars(self) -> Vec<Unit> {
+        let mut units = vec![];
+        for (element, s) in self.pieces {
+            for c in s.chars() {
+                let mut unit = Unit::default();
+                unit.push(element, c);
+                units.push(unit);
+            }
+        }
+        units
+    }
+}
+
+fn units(pieces: &[Piece]) -> Vec<Unit> {
+    let mut units: Vec<Unit> = vec![];
+    let mut before = None;
+    for (element, s) in pieces {
+        for c in s.chars() {
+            let space = c.is_whitespace();
+            let split = match (units.last(), before) {
+                (Some(unit), Some(before)) => {
+                    unit.space != space || (!space && can_break(before, c))
+                }
+                _ => true,
+            };
+            if split {
+                units.push(Unit {
+                    space,
+                    ..Unit::default()
+                });
+            }
+            units.last_mut().unwrap().push(*element, c);
+            before = Some(c);
+        }
+    }
+    units
+}
+
+/// `prefix` then the `pieces`, those of the same element styled together
+fn render<S: Fn(Element, &str) -> String>(
+    prefix: &str,
+    pieces: &[(Option<Element>, String)],
+    style: &S,
+) -> String {
+    let mut merged: Vec<(Option<Element>, String)> = vec![];
+    for (element, s) in pieces.iter().filter(|(_, s)| !s.is_empty()) {
+        match merged.last_mut() {
+            Some((e, last)) if e == element => last.push_str(s),
+            _ => merged.push((*element, s.clone())),
+        }
+    }
+    let mut line = prefix.to_owned();
+    for (element, s) in merged {
+        match element {
+            Some(element) => line.push_str(&style(element, &s)),
+            None => line.push_str(&s),
+        }
+    }
+    line
+}
+
+/// Lay `pieces` out on lines of at most `width` columns, the first line
+/// after `first` and the others after `indent`, styling the pieces with
+/// `style`; all on one line when `width` is `None`
+///
+/// Spaces at the breaks are dropped; a word longer than a line is split.
+pub fn wrap<S: Fn(Element, &str) -> String>(
+    first: &str,
+    indent: &str,
+    pieces: &[Piece],
+    width: Option<usize>,
+    style: S,
+) -> Vec<String> {
+    let Some(width) = width else {
+        let pieces: Vec<_> = pieces.iter().map(|(e, s)| (*e, (*s).to_owned())).collect();
+        return vec![render(first, &pieces, &style)];
+    };
+
+    let mut lines = vec![];
+    let mut prefix = first;
+    let mut line = vec![];
+    let mut used = display_width(first);
+    // whether nothing but spaces is on the line yet
+    let mut fresh = true;
+    let mut space: Option<Unit> = None;
+    let mut queue = units(pieces);
+    queue.reverse();
+    while let Some(unit) = queue.pop() {
+        if unit.space {
+            space = Some(unit);
+            continue;
+        }
+        let before = space.as_ref().map_or(0, |s| s.width);
+        if !fresh && used + before + unit.width > width {
+            lines.push(render(prefix, &line, &style));
+            line.clear();
+            prefix = indent;
+            used = display_width(indent);
+            fresh = true;
+            space = None;
+            queue.push(unit);
+            continue;
+        }
+        if fresh && used + before + unit.width > width && unit.len() > 1 {
+            queue.extend(unit.chars().into_iter().rev());
+            continue;
+        }
+        if let Some(space) = space.take() {
+            used += space.width;
+            line.extend(space.pieces);
+        }
+        used += unit.width;
+        line.extend(unit.pieces);
+        fresh = false;
+    }
+    // spaces at the very end are kept, as when not wrapping
+    if let Some(space) = space {
+        line.extend(space.pieces);
+    }
+    lines.push(render(prefix, &line, &style));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(first: &str, indent: &str, pieces: &[Piece], width: usize) -> Vec<String> {
+        wrap(first, indent, pieces, Some(width), |_, s| s.to_owned())
+    }
+
+    #[test]
+    fn test_wrap() {
+        let pieces = [(Some(Element::Sense), "the quick brown fox jumps")];
+        assert_eq!(
+            vec!["  * the quick", "    brown fox", "    jumps"],
+            plain("  * ", "    ", &pieces, 13)
+        );
+        assert_eq!(
+            vec!["  * the quick brown fox jumps"],
+            plain("  * ", "    ", &pieces, 80)
+        );
+
+        // two columns per CJK character, no line starting with ；
+        let pieces = [(None, "菲力克斯（男子名）；费力克斯制导炸弹")];
+        assert_eq!(
+            vec!["* 菲力克斯（男", "  子名）；费力", "  克斯制导炸弹"],
+            plain("* ", "  ", &pieces, 14)
+        );
+        assert_eq!(
+            vec!["菲力克斯（男子", "名）；费力克斯", "制导炸弹"],
+            plain("", "", &pieces, 15)
+        );
+
+        let pieces = [(None, "see https://example.com/a/long/path")];
+        assert_eq!(
+            vec!["see", "https://ex", "ample.com/", "a/long/pat", "h"],
+            plain("", "", &pieces, 10)
+        );
+    }
+
+    #[test]
+    fn test_wrap_styled() {
+        let pieces = [
+            (Some(Element::WebValue), "费利克斯"),
+            (None, "；"),
+            (Some(Element::WebValue), "菲力克斯"),
+        ];
+        let style = |e: Element, s: &str| format!("<{}>{}</>", e.name(), s);
+        assert_eq!(
+            vec!["  <web-value>费利克斯</>；", "  <web-value>菲力克斯</>"],
+            wrap("  ", "  ", &pieces, Some(12), style)
+        );
+        assert_eq!(
+            vec!["  <web-value>费利克斯</>；<web-value>菲力克斯</>"],
+            wrap("  ", "  ", &pieces, None, style)
+        );
+    }
+}
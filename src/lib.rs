@@ -0,0 +1,70 @@
+//! ydcv: look up words and translate text on YouDao
+//!
+//! The command line tool is a thin consumer of this library. A lookup goes
+//! through the `YdClient` trait, implemented for `Backends` (one HTTP client
+//! per backend, as set up from a `Config`) and for a plain `reqwest` client.
+//! Lookups are async underneath, and the blocking API runs them on a shared
+//! tokio runtime, so it must not be called from an async context. The `async`
+//! feature makes them public: `Backends::lookup` and `Backends::translate` can
+//! then be awaited directly, and `Backends::lookup_many` runs concurrent,
+//! cancellable lookups.
+//!
+//! # Examples
+//!
+//! Look up a word and print its explanation:
+//!
+//! ```no_run
+//! use ydcv::formatters::PlainFormatter;
+//! use ydcv::{Backends, Config, Lang, NetOptions, YdClient};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = Config::load(None)?;
+//! let mut client = Backends::new(&config, &NetOptions::default())?;
+//!
+//! let result = client.lookup_word_lang("hello", Lang::En, Lang::ZhChs, false)?;
+//! if let Some(basic) = result.basic() {
+//!     println!("{} [{}]", result.query(), basic.phonetic().unwrap_or(""));
+//! }
+//! println!("{}", result.explain(&PlainFormatter::new(false)));
+//! # Ok(())
+//! # }
+//! ```
+
+/// Async functions `pub` with the `async` feature, else only for the crate,
+/// whose blocking API runs them
+macro_rules! async_api {
+    ($(#[$meta:meta])* pub async fn $($rest:tt)*) => {
+        #[cfg(feature = "async")]
+        $(#[$meta])*
+        pub async fn $($rest)*
+
+        #[cfg(not(feature = "async"))]
+        $(#[$meta])*
+        pub(crate) async fn $($rest)*
+    };
+}
+
+pub mod backend;
+pub mod cache;
+pub mod config;
+pub mod dict;
+pub mod extract;
+pub mod formatters;
+pub mod lang;
+pub mod layout;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+#[cfg(not(feature = "async"))]
+mod nonblocking;
+pub mod retry;
+pub mod template;
+pub mod theme;
+pub mod translate;
+pub mod ydclient;
+pub mod ydresponse;
+
+pub use crate::backend::{Backend, Backends, NetOptions};
+pub use crate::config::Config;
+pub use crate::lang::Lang;
+pub use crate::ydclient::{BoxError, YdClient, YdClientErr};
+pub use crate::ydresponse::{YdBasic, YdResponse, YdWeb};
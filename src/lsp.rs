@@ -0,0 +1,481 @@
+//! language server answering hovers with lookups, and checking glossary terms
+
+use crate::serve::CacheOptions;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use ydcv::backend::Backends;
+use ydcv::formatters::{Entry, Formatter, MarkdownFormatter};
+use ydcv::lang::Lang;
+use ydcv::ydclient::YdClient;
+use ydcv::ydresponse::YdResponse;
+
+/// JSON-RPC error code of unknown methods
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Diagnostic severity `Warning`
+const WARNING: u8 = 2;
+
+#[derive(StructOpt)]
+pub struct LspOptions {
+    #[structopt(
+        long = "glossary",
+        help = "TOML glossary of approved translations, to warn about the others",
+        parse(from_os_str)
+    )]
+    glossary: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    cache: CacheOptions,
+}
+
+/// A team glossary:
+///
+/// ```toml
+/// [[term]]
+/// term = "cache"
+/// translation = "缓存"
+/// avoid = ["快取", "高速缓存"]
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct Glossary {
+    #[serde(default, rename = "term")]
+    terms: Vec<Term>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Term {
+    term: String,
+    /// the approved translation
+    translation: String,
+    /// translations the team agreed not to use
+    #[serde(default)]
+    avoid: Vec<String>,
+}
+
+impl Glossary {
+    fn load(path: &Path) -> Result<Glossary, Box<dyn Error>> {
+        let s = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(toml::from_str(&s).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// Warnings for every avoided translation in `text`
+    fn diagnostics(&self, text: &str) -> Vec<Value> {
+        let mut diagnostics = vec![];
+        for (line_no, line) in text.lines().enumerate() {
+            for term in &self.terms {
+                for variant in term.avoid.iter().filter(|v| !v.is_empty()) {
+                    for (start, _) in line.match_indices(variant.as_str()) {
+                        let end = start + variant.len();
+                        if !is_standalone(line, start, end) {
+                            continue;
+                        }
+                        diagnostics.push(json!({
+                            "range": range(line_no, utf16_len(&line[..start]), utf16_len(&line[..end])),
+                            "severity": WARNING,
+                            "source": "ydcv",
+                            "message": format!(
+                                "\"{}\" is translated as \"{}\" in the glossary, not \"{}\"",
+                                term.term, term.translation, variant
+                            ),
+                        }));
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+/// Whether `line[start..end]` is not part of a longer latin word; CJK text
+/// has no spaces, so any occurrence counts there
+fn is_standalone(line: &str, start: usize, end: usize) -> bool {
+    let latin = |c: char| c.is_ascii_alphanumeric();
+    let before = line[..start].chars().next_back();
+    let after = line[end..].chars().next();
+    !(before.is_some_and(latin) && line[start..].starts_with(latin)
+        || after.is_some_and(latin) && line[..end].ends_with(latin))
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.chars().map(char::len_utf16).sum()
+}
+
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+/// The word at UTF-16 offset `character` of `line`, and its UTF-16 range
+///
+/// Latin words of identifiers are split, so hovering `lookupWord` on "Word"
+/// gives "Word"; a run of Chinese characters is taken as a whole.
+fn word_at(line: &str, character: usize) -> Option<(String, usize, usize)> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut offset = 0;
+    let cursor = chars.iter().position(|c| {
+        offset += c.len_utf16();
+        offset > character
+    })?;
+
+    let latin = chars[cursor].is_ascii_alphabetic();
+    let same_kind = |c: char| {
+        if latin {
+            c.is_ascii_alphabetic()
+        } else {
+            !c.is_ascii() && c.is_alphabetic()
+        }
+    };
+    if !same_kind(chars[cursor]) {
+        return None;
+    }
+
+    // a lowercase letter followed by an uppercase one starts a new word
+    let boundary =
+        |i: usize| latin && chars[i - 1].is_ascii_lowercase() && chars[i].is_ascii_uppercase();
+    let mut start = cursor;
+    while start > 0 && same_kind(chars[start - 1]) && !boundary(start) {
+        start -= 1;
+    }
+    let mut end = cursor + 1;
+    while end < chars.len() && same_kind(chars[end]) && !boundary(end) {
+        end += 1;
+    }
+
+    let word = chars[start..end].iter().collect::<String>();
+    let utf16 = |chars: &[char]| chars.iter().map(|c| c.len_utf16()).sum::<usize>();
+    Some((word, utf16(&chars[..start]), utf16(&chars[..end])))
+}
+
+/// Hover text of a lookup result, or `None` if it has nothing to show
+fn hover_markdown(response: &YdResponse) -> Option<String> {
+    Entry::new(response).map(|entry| MarkdownFormatter::new().explain(&entry))
+}
+
+/// Read one message: headers up to an empty line, then `Content-Length`
+/// bytes of JSON; `None` once the client closes stdin
+fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(out: &mut dyn Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+/// State of the server; `lookup` looks a word up
+struct Server<L> {
+    glossary: Option<Glossary>,
+    documents: HashMap<String, String>,
+    lookup: L,
+    shutdown: bool,
+}
+
+impl<L> Server<L>
+where
+    L: FnMut(&str) -> Result<YdResponse, Box<dyn Error>>,
+{
+    fn new(glossary: Option<Glossary>, lookup: L) -> Server<L> {
+        Server {
+            glossary,
+            documents: HashMap::new(),
+            lookup,
+            shutdown: false,
+        }
+    }
+
+    /// Messages to send after `uri` changed
+    fn publish_diagnostics(&self, uri: &str) -> Option<Value> {
+        let glossary = self.glossary.as_ref()?;
+        let diagnostics = self
+            .documents
+            .get(uri)
+            .map(|text| glossary.diagnostics(text))
+            .unwrap_or_default();
+        Some(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn hover(&mut self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let line = params["position"]["line"].as_u64().unwrap_or_default() as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or_default() as usize;
+
+        let found = self
+            .documents
+            .get(uri)
+            .and_then(|text| text.lines().nth(line))
+            .and_then(|text| word_at(text, character));
+        let Some((word, start, end)) = found else {
+            return Value::Null;
+        };
+        match (self.lookup)(&word) {
+            Ok(response) => match hover_markdown(&response) {
+                Some(markdown) => json!({
+                    "contents": { "kind": "markdown", "value": markdown },
+                    "range": range(line, start, end),
+                }),
+                None => Value::Null,
+            },
+            Err(err) => {
+                log::debug!("lookup of {} failed: {}", word, err);
+                Value::Null
+            }
+        }
+    }
+
+    /// The result of request `method`
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "hoverProvider": true,
+                    // whole documents, since they are only scanned for glossary terms
+                    "textDocumentSync": { "openClose": true, "change": 1 },
+                },
+                "serverInfo": { "name": "ydcv", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    /// Messages to send for notification `method`
+    fn notification(&mut self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?.to_owned();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str()?;
+                self.documents.insert(uri.clone(), text.to_owned());
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"].as_array()?.last()?["text"].as_str()?;
+                self.documents.insert(uri.clone(), text.to_owned());
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+            }
+            _ => return None,
+        }
+        self.publish_diagnostics(&uri)
+    }
+
+    /// Messages to send in answer to `message`
+    fn handle(&mut self, message: &Value) -> Option<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let Some(id) = message.get("id") else {
+            return self.notification(method, params);
+        };
+        // answers of the client to our requests, which we never send
+        if method.is_empty() {
+            return None;
+        }
+        Some(match self.request(method, params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        })
+    }
+}
+
+/// Run `ydcv lsp` on stdin and stdout until the client sends `exit`
+pub fn run(
+    opts: &LspOptions,
+    client: &Backends,
+    (from, to): (Lang, Lang),
+) -> Result<(), Box<dyn Error>> {
+    let glossary = opts.glossary.as_deref().map(Glossary::load).transpose()?;
+    let cache = opts.cache.cache();
+    let mut client = client.clone();
+    let mut server = Server::new(glossary, |word: &str| {
+        cache.get_or_lookup(word, from, to, || {
+            client.lookup_word_lang(word, from, to, false)
+        })
+    });
+
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    while let Some(message) = read_message(&mut stdin)? {
+        if message["method"] == "exit" {
+            // the protocol asks for 1 when the client did not shut down first
+            if !server.shutdown {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        if let Some(reply) = server.handle(&message) {
+            write_message(&mut stdout, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glossary() -> Glossary {
+        toml::from_str(
+            r#"
+            [[term]]
+            term = "cache"
+            translation = "缓存"
+            avoid = ["快取", "buffer"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_word_at() {
+        let line = "let x = lookupWord(暂时的); // it's";
+        assert_eq!(Some(("lookup".to_owned(), 8, 14)), word_at(line, 8));
+        assert_eq!(Some(("Word".to_owned(), 14, 18)), word_at(line, 16));
+        assert_eq!(Some(("暂时的".to_owned(), 19, 22)), word_at(line, 20));
+        assert_eq!(Some(("it".to_owned(), 28, 30)), word_at(line, 29));
+        assert_eq!(None, word_at(line, 7));
+        assert_eq!(None, word_at(line, 100));
+        // offsets count UTF-16 code units, so the emoji takes two
+        assert_eq!(Some(("ok".to_owned(), 3, 5)), word_at("😀 ok", 4));
+    }
+
+    #[test]
+    fn test_glossary_diagnostics() {
+        let text = "使用快取。\nthe buffers and a buffer";
+        let diagnostics = glossary().diagnostics(text);
+        assert_eq!(2, diagnostics.len(), "{:?}", diagnostics);
+        assert_eq!(range(0, 2, 4), diagnostics[0]["range"]);
+        assert_eq!(
+            "\"cache\" is translated as \"缓存\" in the glossary, not \"快取\"",
+            diagnostics[0]["message"]
+        );
+        assert_eq!(range(1, 18, 24), diagnostics[1]["range"]);
+    }
+
+    #[test]
+    fn test_read_message() {
+        let mut input = &b"Content-Length: 8\r\nContent-Type: x\r\n\r\n{\"id\":1}"[..];
+        assert_eq!(Some(json!({"id": 1})), read_message(&mut input).unwrap());
+        assert_eq!(None, read_message(&mut input).unwrap());
+        assert!(read_message(&mut &b"\r\n{}"[..]).is_err());
+
+        let mut out = vec![];
+        write_message(&mut out, &json!({"id": 1})).unwrap();
+        assert_eq!(b"Content-Length: 8\r\n\r\n{\"id\":1}".to_vec(), out);
+    }
+
+    #[test]
+    fn test_session() {
+        let lookup = |word: &str| match word {
+            "hello" => Ok(YdResponse::new_raw(
+                r#"{"query":"hello","errorCode":0,"translation":["你好"]}"#.to_owned(),
+            )
+            .unwrap()),
+            _ => Err("network is down".into()),
+        };
+        let mut server = Server::new(Some(glossary()), lookup);
+
+        let reply = server
+            .handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}))
+            .unwrap();
+        assert_eq!(
+            json!(true),
+            reply["result"]["capabilities"]["hoverProvider"]
+        );
+        assert_eq!(
+            None,
+            server.handle(&json!({"method": "initialized", "params": {}}))
+        );
+
+        let published = server
+            .handle(&json!({
+                "method": "textDocument/didOpen",
+                "params": {"textDocument": {"uri": "file:///a.md", "text": "hello buffer"}},
+            }))
+            .unwrap();
+        assert_eq!("textDocument/publishDiagnostics", published["method"]);
+        assert_eq!(
+            1,
+            published["params"]["diagnostics"].as_array().unwrap().len()
+        );
+
+        let hover = |server: &mut Server<_>, character| {
+            server.handle(&json!({
+                "id": 2,
+                "method": "textDocument/hover",
+                "params": {
+                    "textDocument": {"uri": "file:///a.md"},
+                    "position": {"line": 0, "character": character},
+                },
+            }))
+        };
+        let reply = hover(&mut server, 2).unwrap();
+        assert_eq!(json!(2), reply["id"]);
+        assert_eq!(
+            json!({
+                "contents": {"kind": "markdown", "value": "## hello\n\n你好"},
+                "range": range(0, 0, 5),
+            }),
+            reply["result"]
+        );
+        assert_eq!(Value::Null, hover(&mut server, 8).unwrap()["result"]);
+
+        let reply = server
+            .handle(&json!({"id": 3, "method": "workspace/symbol", "params": {}}))
+            .unwrap();
+        assert_eq!(json!(METHOD_NOT_FOUND), reply["error"]["code"]);
+
+        let published = server
+            .handle(&json!({
+                "method": "textDocument/didClose",
+                "params": {"textDocument": {"uri": "file:///a.md"}},
+            }))
+            .unwrap();
+        assert_eq!(json!([]), published["params"]["diagnostics"]);
+
+        server.handle(&json!({"id": 4, "method": "shutdown"}));
+        assert!(server.shutdown);
+    }
+}
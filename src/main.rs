@@ -1,45 +1,146 @@
-//! main module of ydcv-rs
+//! command line front-end of the ydcv library
 
 #[cfg(feature = "clipboard")]
 use copypasta::ClipboardContext;
 #[cfg(feature = "clipboard")]
 use copypasta::ClipboardProvider;
-use reqwest::blocking::{Client, ClientBuilder};
 use rustyline::config::Builder;
 use rustyline::history::FileHistory;
 use rustyline::Editor;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
-mod formatters;
-mod lang;
-mod ydclient;
-mod ydresponse;
+mod batch;
+#[cfg(unix)]
+mod daemon;
+mod dictd;
+mod lsp;
+mod native_host;
+mod pager;
+mod selfcheck;
+mod serve;
+mod themes;
 
+use pager::Paged;
+use terminal_size::Width;
+use ydcv::backend::{Backends, NetOptions};
+use ydcv::config::Config;
 #[cfg(windows)]
 #[cfg(feature = "notify")]
-use crate::formatters::WinFormatter;
-use crate::formatters::{AnsiFormatter, Formatter, HtmlFormatter, PlainFormatter};
-use crate::ydclient::YdClient;
+use ydcv::formatters::WinFormatter;
+use ydcv::formatters::{
+    AnsiFormatter, ArticleFormatter, Formatter, HtmlFormatter, JsonFormatter, MarkdownFormatter,
+    PlainFormatter,
+};
+use ydcv::lang::Lang;
+use ydcv::retry::{self, RetryPolicy};
+use ydcv::template::TemplateFormatter;
+use ydcv::theme::{self, Theme};
+use ydcv::translate;
+use ydcv::ydclient::YdClient;
 
-fn lookup_explain(client: &mut Client, word: &str, fmt: &mut dyn Formatter, raw: bool) {
-    if raw {
+fn lookup_explain(
+    client: &mut dyn YdClient,
+    word: &str,
+    (from, to): (Lang, Lang),
+    fmt: &mut dyn Formatter,
+    raw: bool,
+    translate: bool,
+) {
+    if translate || translate::is_text(word) {
+        translate_explain(client, word, (from, to), fmt, raw);
+    } else if raw {
         println!(
             "{}",
-            serde_json::to_string(&client.lookup_word(word, true).unwrap()).unwrap()
+            serde_json::to_string(&client.lookup_word_lang(word, from, to, true).unwrap()).unwrap()
         );
     } else {
-        match client.lookup_word(word, false) {
+        match client.lookup_word_lang(word, from, to, false) {
             Ok(ref result) => {
                 let exp = result.explain(fmt);
                 fmt.print(word, &exp);
             }
-            Err(err) => fmt.print(word, &format!("Error looking-up word {}: {:?}", word, err)),
+            Err(err) => {
+                let exp = format!("Error looking-up word {}: {}", word, err);
+                fmt.print(word, &fmt.error(&exp));
+            }
+        }
+    }
+}
+
+/// Print the article of `word` for GoldenDict; nothing at all when there is no result
+fn goldendict_article(
+    client: &mut dyn YdClient,
+    word: &str,
+    (from, to): (Lang, Lang),
+    fmt: &ArticleFormatter,
+) {
+    match client.lookup_word_lang(word, from, to, false) {
+        Ok(ref result) => {
+            if let Some(article) = fmt.article(result) {
+                println!("{}", article);
+            }
+        }
+        // GoldenDict would show anything printed as an article
+        Err(err) => log::debug!("error looking-up word {}: {}", word, err),
+    }
+}
+
+fn translate_explain(
+    client: &mut dyn YdClient,
+    text: &str,
+    (from, to): (Lang, Lang),
+    fmt: &mut dyn Formatter,
+    raw: bool,
+) {
+    match client.translate_text(text, from, to) {
+        Ok(ref result) if raw => println!("{}", serde_json::to_string(result).unwrap()),
+        Ok(ref result) => {
+            let exp = result.explain(fmt);
+            fmt.print(text, &exp);
+        }
+        Err(err) => {
+            let exp = format!("Error translating text: {}", err);
+            fmt.print(text, &fmt.error(&exp));
         }
     }
 }
 
 #[derive(StructOpt)]
-#[structopt(name = "ydcv", about = "A Rust version of ydcv")]
+enum Command {
+    #[structopt(about = "look up one query per line of a file, or - for stdin")]
+    Batch(batch::BatchOptions),
+    #[structopt(about = "check that the scraping rules still match the result pages")]
+    Selfcheck(selfcheck::SelfcheckOptions),
+    #[structopt(about = "answer GET /lookup?q=&from=&to=&format= over HTTP")]
+    Serve(serve::ServeOptions),
+    #[structopt(about = "serve lookups over the DICT protocol (RFC 2229)")]
+    Dictd(dictd::DictdOptions),
+    #[structopt(about = "language server showing lookups on hover and checking a glossary")]
+    Lsp(lsp::LspOptions),
+    #[structopt(
+        about = "answer a browser extension over native messaging, or install its manifest"
+    )]
+    NativeHost(native_host::NativeHostOptions),
+    #[cfg(unix)]
+    #[structopt(about = "keep a warm client and cache, answering the CLI on a Unix socket")]
+    Daemon(daemon::DaemonOptions),
+    #[structopt(about = "preview the color themes")]
+    Themes(themes::ThemesOptions),
+}
+
+// words that look like a misspelt subcommand (e.g. "hello" for "help") must
+// still be looked up, so do not let clap reject them with a suggestion; nor
+// take "help" itself for the subcommand clap would add
+#[derive(StructOpt)]
+#[structopt(
+    name = "ydcv",
+    about = "A Rust version of ydcv",
+    setting = structopt::clap::AppSettings::AllowExternalSubcommands,
+    setting = structopt::clap::AppSettings::DisableHelpSubcommand
+)]
 struct YdcvOptions {
     #[cfg(feature = "clipboard")]
     #[structopt(
@@ -89,14 +190,101 @@ struct YdcvOptions {
     )]
     raw: bool,
 
+    #[structopt(
+        short = "M",
+        long = "markdown",
+        help = "Markdown output, e.g. to paste into a wiki or a chat",
+        conflicts_with = "html",
+        conflicts_with = "raw",
+        conflicts_with = "notify"
+    )]
+    markdown: bool,
+
+    #[structopt(
+        long = "json",
+        help = "JSON of the explanation: headword, phonetic, senses, web references",
+        conflicts_with = "html",
+        conflicts_with = "raw",
+        conflicts_with = "notify",
+        conflicts_with = "markdown"
+    )]
+    json: bool,
+
+    #[structopt(
+        long = "template",
+        value_name = "NAME|FILE",
+        help = "lay the explanation out with a template: oneline, anki, chat, one in the templates directory next to the configuration file or a file",
+        conflicts_with = "html",
+        conflicts_with = "raw",
+        conflicts_with = "notify",
+        conflicts_with = "markdown",
+        conflicts_with = "json"
+    )]
+    template: Option<String>,
+
+    #[structopt(
+        long = "goldendict",
+        help = "print an HTML article for GoldenDict, or nothing if there is no result",
+        conflicts_with = "html",
+        conflicts_with = "raw"
+    )]
+    goldendict: bool,
+
+    #[structopt(
+        long = "no-stylesheet",
+        help = "leave the embedded stylesheet out of --goldendict articles"
+    )]
+    no_stylesheet: bool,
+
     #[structopt(
         short = "c",
         long = "color",
-        help = "[auto, always, never] use color",
+        help = "[auto, always, never] use color (auto honors NO_COLOR and CLICOLOR_FORCE)",
         default_value = "auto"
     )]
     color: String,
 
+    #[structopt(
+        long = "theme",
+        help = "color theme: dark, light, high-contrast or one of the configuration (see `ydcv themes`)"
+    )]
+    theme: Option<String>,
+
+    #[structopt(
+        long = "no-pager",
+        help = "never page explanations taller than the terminal through $PAGER"
+    )]
+    no_pager: bool,
+
+    #[structopt(
+        short = "w",
+        long = "width",
+        help = "wrap the text to this many columns, 0 not to wrap (default: the width of the terminal)"
+    )]
+    width: Option<usize>,
+
+    #[structopt(
+        short = "f",
+        long = "from",
+        help = "source language code, e.g. en, zh-CHS, ja, ko, fr, de",
+        default_value = "auto"
+    )]
+    from: Lang,
+
+    #[structopt(
+        short = "T",
+        long = "to",
+        help = "target language code, e.g. en, zh-CHS, ja, ko, fr, de",
+        default_value = "auto"
+    )]
+    to: Lang,
+
+    #[structopt(
+        long = "translate",
+        help = "translate sentences and paragraphs instead of looking up words (reads stdin if no WORDS are given)"
+    )]
+    translate: bool,
+
     #[cfg(unix)]
     #[cfg(feature = "notify")]
     #[structopt(
@@ -107,14 +295,82 @@ struct YdcvOptions {
     )]
     timeout: i32,
 
-    #[structopt(value_name = "WORDS")]
+    #[structopt(
+        long = "connect-timeout",
+        help = "seconds to wait for a connection to a backend",
+        default_value = "5"
+    )]
+    connect_timeout: f64,
+
+    #[structopt(
+        long = "read-timeout",
+        help = "seconds to wait for any part of the answer of a backend",
+        default_value = "10"
+    )]
+    read_timeout: f64,
+
+    #[structopt(
+        long = "retries",
+        help = "retries of a request after transient errors (5xx, 429, timeouts, resets)",
+        default_value = "2"
+    )]
+    retries: u32,
+
+    #[structopt(
+        long = "config",
+        help = "configuration file (default: $XDG_CONFIG_HOME/ydcv/config.toml)",
+        parse(from_os_str)
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long = "proxy",
+        help = "proxy for all backends (http://, https:// or socks5://[user:pass@]host:port, or direct)"
+    )]
+    proxy: Option<String>,
+
+    #[structopt(
+        long = "no-proxy",
+        help = "comma-separated hosts to reach without a proxy",
+        use_delimiter = true
+    )]
+    no_proxy: Vec<String>,
+
+    #[cfg(unix)]
+    #[structopt(
+        long = "no-daemon",
+        help = "look words up directly even if `ydcv daemon` is running"
+    )]
+    no_daemon: bool,
+
+    #[structopt(
+        value_name = "WORDS",
+        help = "words to look up; after -- when one is named like a subcommand, e.g. `ydcv -- serve`"
+    )]
     free: Vec<String>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 }
 
 fn main() {
     env_logger::init();
 
-    let ydcv_options = YdcvOptions::from_args();
+    let matches = YdcvOptions::clap().get_matches();
+    let ydcv_options = YdcvOptions::from_clap(&matches);
+    // "ydcv hello serve" would otherwise start the server
+    if let (Some(name), false) = (matches.subcommand_name(), ydcv_options.free.is_empty()) {
+        structopt::clap::Error::with_description(
+            &format!(
+                "the subcommand '{}' cannot follow WORDS; look it up with `ydcv -- {} {}`",
+                name,
+                ydcv_options.free.join(" "),
+                name
+            ),
+            structopt::clap::ErrorKind::UnexpectedMultipleUsage,
+        )
+        .exit();
+    }
 
     #[cfg(feature = "notify")]
     let notify_enabled = ydcv_options.notify;
@@ -130,12 +386,132 @@ fn main() {
     #[cfg(not(feature = "clipboard"))]
     let selection_enabled = false;
 
-    // reqwest will use HTTPS_PROXY env automatically
-    let mut client = ClientBuilder::new().build().unwrap();
+    retry::set_policy(RetryPolicy {
+        retries: ydcv_options.retries,
+        ..RetryPolicy::default()
+    });
+
+    let config = Config::load(ydcv_options.config.as_deref()).unwrap_or_else(|err| {
+        eprintln!("ydcv: cannot load configuration: {}", err);
+        std::process::exit(1);
+    });
+
+    let net = NetOptions {
+        connect_timeout: Duration::from_secs_f64(ydcv_options.connect_timeout),
+        read_timeout: Duration::from_secs_f64(ydcv_options.read_timeout),
+        proxy: ydcv_options.proxy.clone(),
+        no_proxy: ydcv_options.no_proxy.clone(),
+    };
+    let client = Backends::new(&config, &net).unwrap_or_else(|err| {
+        eprintln!("ydcv: {}", err);
+        std::process::exit(1);
+    });
+
+    let langs = (ydcv_options.from, ydcv_options.to);
+
+    if let Some(Command::Batch(ref opts)) = ydcv_options.cmd {
+        if let Err(err) = batch::run(opts, &client, langs) {
+            eprintln!("ydcv: batch lookup failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Serve(ref opts)) = ydcv_options.cmd {
+        if let Err(err) = serve::run(opts, &client) {
+            eprintln!("ydcv: cannot serve: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Dictd(ref opts)) = ydcv_options.cmd {
+        if let Err(err) = dictd::run(opts, &client, langs) {
+            eprintln!("ydcv: cannot serve DICT: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Lsp(ref opts)) = ydcv_options.cmd {
+        if let Err(err) = lsp::run(opts, &client, langs) {
+            eprintln!("ydcv: language server failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::NativeHost(ref opts)) = ydcv_options.cmd {
+        if let Err(err) = native_host::run(opts, &client) {
+            eprintln!("ydcv: native messaging host failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    #[cfg(unix)]
+    if let Some(Command::Daemon(ref opts)) = ydcv_options.cmd {
+        if let Err(err) = daemon::run(opts, &client) {
+            eprintln!("ydcv: cannot start the daemon: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Themes(ref opts)) = ydcv_options.cmd {
+        let color = theme::use_color(&ydcv_options.color, atty::is(atty::Stream::Stdout));
+        if let Err(err) = themes::run(opts, &config, color) {
+            eprintln!("ydcv: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Selfcheck(ref opts)) = ydcv_options.cmd {
+        match selfcheck::run(opts, &client) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                eprintln!("ydcv: self-check failed: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let result_page = client.scraper.url.clone();
+    #[cfg(unix)]
+    let mut client = daemon::DaemonClient::new(
+        client,
+        (!ydcv_options.no_daemon).then(daemon::default_socket),
+    );
+    #[cfg(not(unix))]
+    let mut client = client;
+
+    if ydcv_options.goldendict {
+        let fmt = ArticleFormatter::new(!ydcv_options.no_stylesheet);
+        for word in &ydcv_options.free {
+            goldendict_article(&mut client, word.trim(), langs, &fmt);
+        }
+        return;
+    }
 
     let mut html = HtmlFormatter::new(notify_enabled);
-    let mut ansi = AnsiFormatter::new(notify_enabled);
+    let mut ansi;
     let mut plain = PlainFormatter::new(notify_enabled);
+    let width = match ydcv_options.width {
+        Some(0) => None,
+        Some(width) => Some(width),
+        None => terminal_size::terminal_size().map(|(Width(w), _)| w as usize),
+    };
+    plain.set_width(width);
+    let mut markdown = MarkdownFormatter::new();
+    let mut json = JsonFormatter::new();
+    let mut template = ydcv_options.template.as_deref().map(|name| {
+        TemplateFormatter::load(name, &config).unwrap_or_else(|err| {
+            eprintln!("ydcv: {}", err);
+            std::process::exit(1);
+        })
+    });
     #[cfg(windows)]
     #[cfg(feature = "notify")]
     let mut win = WinFormatter::new(notify_enabled);
@@ -144,27 +520,67 @@ fn main() {
     #[cfg(feature = "notify")]
     html.set_timeout(ydcv_options.timeout * 1000);
 
-    let fmt: &mut dyn Formatter =
-        if ydcv_options.html || (notify_enabled && cfg!(unix) && cfg!(feature = "notify")) {
-            &mut html
-        } else if notify_enabled {
-            #[cfg(all(windows, feature = "notify"))]
-            {
-                &mut win
-            }
-            #[cfg(not(all(windows, feature = "notify")))]
-            {
-                &mut plain
-            }
-        } else if ydcv_options.color == "always"
-            || atty::is(atty::Stream::Stdout) && ydcv_options.color != "never"
+    let fmt: &mut dyn Formatter = if let Some(ref mut template) = template {
+        template
+    } else if ydcv_options.markdown {
+        &mut markdown
+    } else if ydcv_options.json {
+        &mut json
+    } else if ydcv_options.html || (notify_enabled && cfg!(unix) && cfg!(feature = "notify")) {
+        &mut html
+    } else if notify_enabled {
+        #[cfg(all(windows, feature = "notify"))]
+        {
+            &mut win
+        }
+        #[cfg(not(all(windows, feature = "notify")))]
         {
-            &mut ansi
-        } else {
             &mut plain
-        };
+        }
+    } else if theme::use_color(&ydcv_options.color, atty::is(atty::Stream::Stdout)) {
+        // a bad theme does not matter unless it is used
+        let theme = ydcv_options
+            .theme
+            .as_deref()
+            .or(config.theme.as_deref())
+            .unwrap_or("dark");
+        ansi = AnsiFormatter::with_theme(Theme::load(theme, &config).unwrap_or_else(|err| {
+            eprintln!("ydcv: {}", err);
+            std::process::exit(1);
+        }));
+        ansi.set_width(width);
+        let hyperlinks = config
+            .hyperlinks
+            .unwrap_or_else(|| theme::has_hyperlinks() && atty::is(atty::Stream::Stdout));
+        ansi.set_hyperlinks(hyperlinks.then_some(result_page));
+        &mut ansi
+    } else {
+        &mut plain
+    };
+
+    // the watcher of the selection would wait for the pager to be quit
+    let watching = selection_enabled && ydcv_options.free.is_empty();
+    let mut paged;
+    let fmt: &mut dyn Formatter = match pager::command(config.pager.as_deref()) {
+        Some(command)
+            if !ydcv_options.no_pager
+                && !notify_enabled
+                && !watching
+                && atty::is(atty::Stream::Stdout) =>
+        {
+            paged = Paged::new(fmt, command, config.hyperlinks == Some(true));
+            &mut paged
+        }
+        _ => fmt,
+    };
+
+    let translate = ydcv_options.translate;
 
-    if ydcv_options.free.is_empty() {
+    if ydcv_options.free.is_empty() && translate && !atty::is(atty::Stream::Stdin) {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text).unwrap();
+        translate_explain(&mut client, &text, langs, fmt, ydcv_options.raw);
+    } else if ydcv_options.free.is_empty() {
         if selection_enabled {
             #[cfg(feature = "clipboard")]
             {
@@ -179,7 +595,14 @@ fn main() {
                         let curr = curr.trim_matches('\u{0}').trim();
                         if !curr.is_empty() && last != curr {
                             last = curr.to_owned();
-                            lookup_explain(&mut client, curr, fmt, ydcv_options.raw);
+                            lookup_explain(
+                                &mut client,
+                                curr,
+                                langs,
+                                fmt,
+                                ydcv_options.raw,
+                                translate,
+                            );
                             println!("Waiting for selection> ");
                         }
                     }
@@ -194,13 +617,44 @@ fn main() {
             while let Ok(w) = reader.readline("> ") {
                 let word = w.trim();
                 if !word.is_empty() {
-                    lookup_explain(&mut client, word, fmt, ydcv_options.raw);
+                    lookup_explain(&mut client, word, langs, fmt, ydcv_options.raw, translate);
                 }
             }
         }
     } else {
         for word in ydcv_options.free {
-            lookup_explain(&mut client, word.trim(), fmt, ydcv_options.raw);
+            lookup_explain(
+                &mut client,
+                word.trim(),
+                langs,
+                fmt,
+                ydcv_options.raw,
+                translate,
+            );
         }
     }
+
+    if template.is_some_and(|template| template.errors() > 0) {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_words() {
+        let parse = |args: &[&str]| {
+            let matches = YdcvOptions::clap().get_matches_from_safe(args).unwrap();
+            let options = YdcvOptions::from_clap(&matches);
+            (options.free, matches.subcommand_name().map(str::to_owned))
+        };
+        assert_eq!((vec!["help".to_owned()], None), parse(&["ydcv", "help"]));
+        assert_eq!((vec!["hello".to_owned()], None), parse(&["ydcv", "hello"]));
+        assert_eq!(
+            (vec![], Some("themes".to_owned())),
+            parse(&["ydcv", "themes"])
+        );
+    }
 }
@@ -0,0 +1,415 @@
+//! WebExtension native messaging host, and the installer of its manifest
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+use ydcv::backend::Backends;
+use ydcv::formatters::ArticleFormatter;
+use ydcv::lang::Lang;
+use ydcv::ydclient::YdClient;
+use ydcv::ydresponse::YdResponse;
+
+/// Name of the host, as given to `runtime.connectNative`
+const HOST_NAME: &str = "ydcv";
+
+/// Largest message accepted from the browser; Chromium caps them at 4 GB,
+/// but a lookup request is a few bytes
+const MAX_MESSAGE: u32 = 1024 * 1024;
+
+#[derive(StructOpt)]
+pub struct NativeHostOptions {
+    #[structopt(subcommand)]
+    cmd: Option<NativeHostCommand>,
+}
+
+#[derive(StructOpt)]
+enum NativeHostCommand {
+    #[structopt(about = "write the host manifest for Firefox and Chromium-based browsers")]
+    Install(InstallOptions),
+    /// the browsers start the host with the manifest path or the caller's origin
+    #[structopt(external_subcommand)]
+    Browser(Vec<String>),
+}
+
+#[derive(StructOpt)]
+struct InstallOptions {
+    #[structopt(
+        long = "browser",
+        help = "[firefox, chrome, chromium] browser to install for (default: all of them)"
+    )]
+    browsers: Vec<Browser>,
+
+    #[structopt(
+        long = "extension-id",
+        help = "ID of an extension allowed to use the host",
+        required = true
+    )]
+    extension_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Browser {
+    Firefox,
+    Chrome,
+    Chromium,
+}
+
+impl FromStr for Browser {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Browser, String> {
+        match s {
+            "firefox" => Ok(Browser::Firefox),
+            "chrome" => Ok(Browser::Chrome),
+            "chromium" => Ok(Browser::Chromium),
+            _ => Err(format!(
+                "unknown browser '{}', expected one of: firefox, chrome, chromium",
+                s
+            )),
+        }
+    }
+}
+
+impl Browser {
+    const ALL: [Browser; 3] = [Browser::Firefox, Browser::Chrome, Browser::Chromium];
+
+    /// Directory the browser looks for host manifests in
+    #[cfg(target_os = "macos")]
+    fn manifest_dir(self) -> Option<PathBuf> {
+        let support = dirs::home_dir()?.join("Library/Application Support");
+        Some(match self {
+            Browser::Firefox => support.join("Mozilla/NativeMessagingHosts"),
+            Browser::Chrome => support.join("Google/Chrome/NativeMessagingHosts"),
+            Browser::Chromium => support.join("Chromium/NativeMessagingHosts"),
+        })
+    }
+
+    /// Directory the browser looks for host manifests in
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn manifest_dir(self) -> Option<PathBuf> {
+        Some(match self {
+            Browser::Firefox => dirs::home_dir()?.join(".mozilla/native-messaging-hosts"),
+            Browser::Chrome => dirs::config_dir()?.join("google-chrome/NativeMessagingHosts"),
+            Browser::Chromium => dirs::config_dir()?.join("chromium/NativeMessagingHosts"),
+        })
+    }
+
+    /// Directory of the manifest, which the registry then points to
+    #[cfg(windows)]
+    fn manifest_dir(self) -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("ydcv").join(match self {
+            Browser::Firefox => "firefox",
+            Browser::Chrome | Browser::Chromium => "chromium",
+        }))
+    }
+
+    /// The manifest, allowing `extension_ids` to connect to `path`
+    fn manifest(self, path: &Path, extension_ids: &[String]) -> serde_json::Value {
+        let mut manifest = json!({
+            "name": HOST_NAME,
+            "description": "ydcv dictionary lookups",
+            "path": path,
+            "type": "stdio",
+        });
+        if self == Browser::Firefox {
+            manifest["allowed_extensions"] = json!(extension_ids);
+        } else {
+            let origins = extension_ids
+                .iter()
+                .map(|id| format!("chrome-extension://{}/", id))
+                .collect::<Vec<_>>();
+            manifest["allowed_origins"] = json!(origins);
+        }
+        manifest
+    }
+}
+
+/// One lookup request from the extension
+#[derive(Deserialize)]
+struct Request {
+    /// echoed back, so that the extension can match replies to requests
+    id: Option<serde_json::Value>,
+    q: String,
+    /// language codes, `auto` when missing
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// The answer to a request: the result and its article, or an error
+#[derive(Serialize)]
+struct Reply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<YdResponse>,
+    /// `null` when there is no result for the query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Reply {
+    fn error(id: Option<serde_json::Value>, error: String) -> Reply {
+        Reply {
+            id,
+            result: None,
+            html: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Read one message: its length in native byte order, then as many bytes of
+/// JSON; `None` once the browser closes stdin
+fn read_message(input: &mut dyn Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = u32::from_ne_bytes(len);
+    if len > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too long", len),
+        ));
+    }
+    let mut message = vec![0; len as usize];
+    input.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message(out: &mut dyn Write, message: &[u8]) -> io::Result<()> {
+    out.write_all(&(message.len() as u32).to_ne_bytes())?;
+    out.write_all(message)?;
+    out.flush()
+}
+
+fn parse_lang(code: &Option<String>) -> Result<Lang, String> {
+    code.as_deref().unwrap_or("auto").parse()
+}
+
+/// Answer the request `message` using `lookup`
+fn answer<L>(message: &[u8], mut lookup: L) -> Reply
+where
+    L: FnMut(&str, Lang, Lang) -> Result<YdResponse, Box<dyn Error>>,
+{
+    let request: Request = match serde_json::from_slice(message) {
+        Ok(request) => request,
+        Err(err) => return Reply::error(None, format!("invalid request: {}", err)),
+    };
+    let (from, to) = match (parse_lang(&request.from), parse_lang(&request.to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(err), _) | (_, Err(err)) => return Reply::error(request.id, err),
+    };
+
+    match lookup(request.q.trim(), from, to) {
+        Ok(response) => Reply {
+            id: request.id,
+            html: Some(ArticleFormatter::new(true).article(&response)),
+            result: Some(response),
+            error: None,
+        },
+        Err(err) => Reply::error(request.id, err.to_string()),
+    }
+}
+
+/// Answer requests on stdin until the browser closes it
+fn serve(client: &Backends) -> Result<(), Box<dyn Error>> {
+    let mut client = client.clone();
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    while let Some(message) = read_message(&mut stdin)? {
+        let reply = answer(&message, |word, from, to| {
+            client.lookup_word_lang(word, from, to, false)
+        });
+        write_message(&mut stdout, &serde_json::to_vec(&reply)?)?;
+    }
+    Ok(())
+}
+
+/// Write a launcher passing `native-host` to this executable, since
+/// manifests cannot give arguments to the host
+fn write_launcher(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let exe = env::current_exe()?;
+    fs::create_dir_all(dir)?;
+
+    #[cfg(unix)]
+    let path = {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("ydcv-native-host");
+        let exe = exe.to_string_lossy().replace('\'', r"'\''");
+        fs::write(
+            &path,
+            format!("#!/bin/sh\nexec '{}' native-host \"$@\"\n", exe),
+        )?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        path
+    };
+    #[cfg(windows)]
+    let path = {
+        let path = dir.join("ydcv-native-host.bat");
+        fs::write(
+            &path,
+            format!("@echo off\r\n\"{}\" native-host %*\r\n", exe.display()),
+        )?;
+        path
+    };
+
+    Ok(path)
+}
+
+/// Point the registry of `browser` to `manifest`, which is how Windows
+/// browsers find hosts
+#[cfg(windows)]
+fn register(browser: Browser, manifest: &Path) -> Result<(), Box<dyn Error>> {
+    let key = match browser {
+        Browser::Firefox => r"HKCU\Software\Mozilla\NativeMessagingHosts\",
+        Browser::Chrome => r"HKCU\Software\Google\Chrome\NativeMessagingHosts\",
+        Browser::Chromium => r"HKCU\Software\Chromium\NativeMessagingHosts\",
+    };
+    let status = std::process::Command::new("reg")
+        .args([
+            "add",
+            &format!("{}{}", key, HOST_NAME),
+            "/ve",
+            "/t",
+            "REG_SZ",
+        ])
+        .arg("/d")
+        .arg(manifest)
+        .arg("/f")
+        .status()?;
+    if !status.success() {
+        return Err(format!("reg add {} failed", key).into());
+    }
+    Ok(())
+}
+
+fn install(opts: &InstallOptions) -> Result<(), Box<dyn Error>> {
+    let data_dir = dirs::data_dir().ok_or("cannot find the data directory")?;
+    let launcher = write_launcher(&data_dir.join("ydcv"))?;
+
+    let browsers = if opts.browsers.is_empty() {
+        &Browser::ALL[..]
+    } else {
+        &opts.browsers[..]
+    };
+    for &browser in browsers {
+        let dir = browser
+            .manifest_dir()
+            .ok_or("cannot find the home directory")?;
+        let path = dir.join(format!("{}.json", HOST_NAME));
+        let manifest = browser.manifest(&launcher, &opts.extension_ids);
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&path, serde_json::to_string_pretty(&manifest)?))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        #[cfg(windows)]
+        register(browser, &path)?;
+        eprintln!("ydcv: wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Run `ydcv native-host`, or its installer
+pub fn run(opts: &NativeHostOptions, client: &Backends) -> Result<(), Box<dyn Error>> {
+    match opts.cmd {
+        Some(NativeHostCommand::Install(ref install_opts)) => install(install_opts),
+        Some(NativeHostCommand::Browser(ref args)) => {
+            log::debug!("native messaging host started with {:?}", args);
+            serve(client)
+        }
+        None => serve(client),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(json: &str) -> Vec<u8> {
+        let mut message = vec![];
+        write_message(&mut message, json.as_bytes()).unwrap();
+        message
+    }
+
+    #[test]
+    fn test_read_message() {
+        let mut input = [message(r#"{"q":"hello"}"#), message("{}")].concat();
+        input.extend_from_slice(&[1, 0]);
+        let mut input = &input[..];
+
+        assert_eq!(
+            Some(br#"{"q":"hello"}"#.to_vec()),
+            read_message(&mut input).unwrap()
+        );
+        assert_eq!(Some(b"{}".to_vec()), read_message(&mut input).unwrap());
+        // a truncated length is the end of the input
+        assert_eq!(None, read_message(&mut input).unwrap());
+        assert!(read_message(&mut &u32::MAX.to_ne_bytes()[..]).is_err());
+    }
+
+    #[test]
+    fn test_answer() {
+        let lookup = |word: &str, from: Lang, _: Lang| match (word, from) {
+            ("hello", Lang::En) => Ok(YdResponse::new_raw(
+                r#"{"query":"hello","errorCode":0,"translation":["你好"]}"#.to_owned(),
+            )
+            .unwrap()),
+            ("hello", _) => Err("network is down".into()),
+            _ => Ok(YdResponse::new_raw(r#"{"query":"xyzzy","errorCode":0}"#.to_owned()).unwrap()),
+        };
+
+        let reply = answer(br#"{"id":7,"q":" hello ","from":"en"}"#, lookup);
+        let reply = serde_json::to_value(reply).unwrap();
+        assert_eq!(json!(7), reply["id"]);
+        assert_eq!(json!(["你好"]), reply["result"]["translation"]);
+        assert!(reply["html"]
+            .as_str()
+            .unwrap()
+            .contains("<span class=\"ydcv-translation\">你好</span>"));
+
+        let reply = serde_json::to_value(answer(br#"{"q":"xyzzy"}"#, lookup)).unwrap();
+        assert_eq!(json!(null), reply["html"]);
+        assert_eq!(None, reply.get("id"));
+
+        let reply = serde_json::to_value(answer(br#"{"q":"hello"}"#, lookup)).unwrap();
+        assert_eq!(json!({"error": "network is down"}), reply);
+        let reply = serde_json::to_value(answer(br#"{"q":"hello","to":"xx"}"#, lookup)).unwrap();
+        assert!(reply["error"].is_string());
+        let reply = serde_json::to_value(answer(b"hello", lookup)).unwrap();
+        assert!(reply["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid request"));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let path = Path::new("/opt/ydcv-native-host");
+        let ids = ["ydcv@example.org".to_owned()];
+        assert_eq!(
+            json!({
+                "name": "ydcv",
+                "description": "ydcv dictionary lookups",
+                "path": "/opt/ydcv-native-host",
+                "type": "stdio",
+                "allowed_extensions": ["ydcv@example.org"],
+            }),
+            Browser::Firefox.manifest(path, &ids)
+        );
+        assert_eq!(
+            json!(["chrome-extension://ydcv@example.org/"]),
+            Browser::Chromium.manifest(path, &ids)["allowed_origins"]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! async lookups on tokio, which the blocking `YdClient` API drives on a shared runtime
+
+use crate::backend::{result_query, Backend, Backends};
+use crate::lang::{resolve, Lang};
+use crate::translate::{split, Translation, MAX_CHUNK_CHARS};
+use crate::ydclient::{decode_result, get_salt, get_sign, new_api_keys, BoxError, YdClientErr};
+use crate::ydresponse::YdResponse;
+#[cfg(feature = "async")]
+use futures::stream::{self, StreamExt};
+use log::debug;
+use once_cell::sync::Lazy;
+use std::future::Future;
+use tokio::runtime::{Builder, Runtime};
+#[cfg(feature = "async")]
+use tokio_util::sync::CancellationToken;
+
+/// Runtime of the blocking API, shared by every thread that uses it
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("ydcv-runtime")
+        .enable_all()
+        .build()
+        .expect("cannot start the tokio runtime")
+});
+
+/// Run `future` to completion on the shared runtime
+///
+/// Like `reqwest::blocking`, this panics when called from an async context.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    RUNTIME.block_on(future)
+}
+
+impl Backends {
+    async_api! {
+        /// Look up a word on the youdao.com result page, falling back to the
+        /// openapi, along with its definitions on the DICT server if there is one
+        ///
+        /// With `raw`, the openapi answer is kept as it is instead of being decoded.
+        pub async fn lookup(
+            &self,
+            word: &str,
+            from: Lang,
+            to: Lang,
+            raw: bool,
+        ) -> Result<YdResponse, BoxError> {
+            let result = self.lookup_youdao(word, from, to, raw).await;
+            let Some(dict) = &self.dict else {
+                return result;
+            };
+
+            let definitions = dict.define(word).await.unwrap_or_else(|err| {
+                debug!("DICT lookup of {} failed: {}", word, err);
+                vec![]
+            });
+            if definitions.is_empty() {
+                return result;
+            }
+            // words of in-house glossaries are often unknown to Youdao
+            Ok(match result {
+                Ok(mut response) if response.is_ok() => {
+                    response.add_definitions(definitions);
+                    response
+                }
+                _ => YdResponse::from_definitions(word, definitions),
+            })
+        }
+    }
+
+    async fn lookup_youdao(
+        &self,
+        word: &str,
+        from: Lang,
+        to: Lang,
+        raw: bool,
+    ) -> Result<YdResponse, BoxError> {
+        let (from, to) = resolve(word, from, to);
+
+        let old_api_err = match self.lookup_page(word, from, to).await {
+            Ok(response) => return Ok(response),
+            Err(err) => err,
+        };
+
+        self.lookup_api(word, from, to, raw)
+            .await
+            .map_err(|new_api_err| {
+                Box::new(YdClientErr::NewAndOldAPIError(new_api_err, old_api_err)) as BoxError
+            })
+    }
+
+    async_api! {
+        /// Look up a word on the backend called `name` in the config file only,
+        /// `dict` for the DICT server
+        pub async fn lookup_backend(
+            &self,
+            name: &str,
+            word: &str,
+            from: Lang,
+            to: Lang,
+        ) -> Result<YdResponse, BoxError> {
+            let (from, to) = resolve(word, from, to);
+            if name == self.scraper.name {
+                self.lookup_page(word, from, to).await
+            } else if name == self.openapi.name {
+                self.lookup_api(word, from, to, false).await
+            } else if let Some(dict) = self.dict.as_ref().filter(|_| name == "dict") {
+                Ok(YdResponse::from_definitions(word, dict.define(word).await?))
+            } else {
+                Err(format!("unknown backend {}", name).into())
+            }
+        }
+    }
+
+    async fn lookup_page(&self, word: &str, from: Lang, to: Lang) -> Result<YdResponse, BoxError> {
+        let body = lookup_word(word, from, to, &self.scraper).await?;
+        Ok(YdResponse::from_html(&body, word, &self.rules))
+    }
+
+    async fn lookup_api(
+        &self,
+        word: &str,
+        from: Lang,
+        to: Lang,
+        raw: bool,
+    ) -> Result<YdResponse, BoxError> {
+        let body = lookup_word_new_api(word, from, to, &self.openapi).await?;
+        if raw {
+            YdResponse::new_raw(body).map_err(Into::into)
+        } else {
+            decode_result(&body).map_err(Into::into)
+        }
+    }
+
+    async_api! {
+        /// Translate a whole text through the openapi, keeping its paragraphs aligned
+        pub async fn translate(
+            &self,
+            text: &str,
+            from: Lang,
+            to: Lang,
+        ) -> Result<Translation, BoxError> {
+            let (from, to) = resolve(text, from, to);
+
+            let mut parts = vec![];
+            for (source, chunks) in split(text, MAX_CHUNK_CHARS) {
+                let mut translations = vec![];
+                for chunk in &chunks {
+                    translations.push(translate_new_api(chunk, from, to, &self.openapi).await?);
+                }
+                parts.push((source, translations));
+            }
+
+            Ok(Translation::from_parts(parts, to))
+        }
+    }
+
+    /// Look up `words` with at most `concurrency` lookups in flight, returning
+    /// the results in the order of `words`
+    ///
+    /// Once `cancel` is cancelled, the lookups in flight are dropped and every
+    /// word left fails with `YdClientErr::Cancelled`.
+    ///
+    /// ```no_run
+    /// # async fn f() {
+    /// use tokio_util::sync::CancellationToken;
+    /// use ydcv::{Backends, Lang};
+    ///
+    /// let backends = Backends::with_client(reqwest::Client::new());
+    /// let cancel = CancellationToken::new();
+    /// let results = backends
+    ///     .lookup_many(["hello", "world"], Lang::Auto, Lang::Auto, 4, &cancel)
+    ///     .await;
+    /// # }
+    /// ```
+    #[cfg(feature = "async")]
+    pub async fn lookup_many<I, S>(
+        &self,
+        words: I,
+        from: Lang,
+        to: Lang,
+        concurrency: usize,
+        cancel: &CancellationToken,
+    ) -> Vec<Result<YdResponse, BoxError>>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        stream::iter(words)
+            .map(|word| async move {
+                tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => Err(Box::new(YdClientErr::Cancelled) as BoxError),
+                    result = self.lookup(word.as_ref(), from, to, false) => result,
+                }
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+}
+
+/// Fetch the youdao.com result page of `word`
+pub async fn lookup_word(
+    word: &str,
+    from: Lang,
+    to: Lang,
+    backend: &Backend,
+) -> Result<String, BoxError> {
+    if !Lang::scraper_supports(from, to) {
+        return Err(Box::new(YdClientErr::UnsupportedLangPair(from, to)));
+    }
+
+    backend.fetch(|| backend.get(&result_query(word))).await
+}
+
+async fn lookup_word_new_api(
+    word: &str,
+    from: Lang,
+    to: Lang,
+    backend: &Backend,
+) -> Result<String, BoxError> {
+    let (new_api_key, new_app_sec) = new_api_keys()?;
+
+    let salt = get_salt();
+    let sign = get_sign(new_api_key, word, &salt, new_app_sec);
+
+    backend
+        .fetch(|| {
+            backend.get(&[
+                ("appKey", new_api_key),
+                ("q", word),
+                ("from", from.code()),
+                ("to", to.code()),
+                ("salt", &salt),
+                ("sign", &sign),
+            ])
+            // .header(Connection::close())
+        })
+        .await
+}
+
+async fn translate_new_api(
+    text: &str,
+    from: Lang,
+    to: Lang,
+    backend: &Backend,
+) -> Result<String, BoxError> {
+    let (new_api_key, new_app_sec) = new_api_keys()?;
+
+    let salt = get_salt();
+    let sign = get_sign(new_api_key, text, &salt, new_app_sec);
+
+    // long texts do not fit in a query string, so post them as a form
+    let body = backend
+        .fetch(|| {
+            backend.post_form(&[
+                ("appKey", new_api_key),
+                ("q", text),
+                ("from", from.code()),
+                ("to", to.code()),
+                ("salt", &salt),
+                ("sign", &sign),
+            ])
+        })
+        .await?;
+
+    let response = YdResponse::new_raw(body)?;
+    if !response.is_ok() {
+        return Err(Box::new(YdClientErr::ApiError(
+            response.error_code().to_string(),
+        )));
+    }
+
+    Ok(response.translation().unwrap_or_default().join(""))
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_futures_are_send() {
+        fn assert_send<T: Send>(_: T) {}
+
+        let backends = Backends::with_client(reqwest::Client::new());
+        let cancel = CancellationToken::new();
+        assert_send(backends.lookup("hello", Lang::Auto, Lang::Auto, false));
+        assert_send(backends.translate("hello", Lang::Auto, Lang::Auto));
+        assert_send(backends.lookup_many(["hello"], Lang::Auto, Lang::Auto, 1, &cancel));
+    }
+
+    #[test]
+    fn test_lookup_many_cancelled() {
+        let backends = Backends::with_client(reqwest::Client::new());
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let results =
+            block_on(backends.lookup_many(["hello", "world"], Lang::Auto, Lang::Auto, 2, &cancel));
+        assert_eq!(2, results.len());
+        for result in results {
+            assert_eq!(
+                YdClientErr::Cancelled.to_string(),
+                result.unwrap_err().to_string()
+            );
+        }
+    }
+}
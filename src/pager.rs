@@ -0,0 +1,213 @@
+//! paging of the explanations taller than the terminal
+
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use terminal_size::{terminal_size, Height, Width};
+use ydcv::formatters::{Element, Entry, Formatter};
+use ydcv::layout::display_width;
+use ydcv::translate::Translation;
+
+/// Pager run unless `$PAGER` or the configuration gives another one
+const DEFAULT_PAGER: &str = "less -R";
+
+/// Formatter paging what `inner` prints when it does not fit the terminal
+pub struct Paged<'a> {
+    inner: &'a mut dyn Formatter,
+    command: String,
+    /// whether hyperlinks are kept in the pager instead of stripped
+    links: bool,
+}
+
+/// The `configured` pager, else `$PAGER`, else `less -R`; `None` when it is
+/// empty or `cat`, which turns paging off
+pub fn command(configured: Option<&str>) -> Option<String> {
+    let command = match configured {
+        Some(command) => command.to_owned(),
+        None => env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_owned()),
+    };
+    let command = command.trim();
+    (!command.is_empty() && command != "cat").then(|| command.to_owned())
+}
+
+impl<'a> Paged<'a> {
+    pub fn new(inner: &'a mut dyn Formatter, command: String, links: bool) -> Paged<'a> {
+        Paged {
+            inner,
+            command,
+            links,
+        }
+    }
+
+    /// Run the pager on `body`, or tell why it could not run
+    fn page(&self, body: &str) -> std::io::Result<()> {
+        #[cfg(unix)]
+        let mut command = {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(&self.command);
+            command
+        };
+        #[cfg(not(unix))]
+        let mut command = {
+            let mut words = self.command.split_whitespace();
+            let mut command = Command::new(words.next().unwrap_or_default());
+            command.args(words);
+            command
+        };
+        // colors as they are, and the text left on the screen after quitting
+        if env::var_os("LESS").is_none() {
+            command.env("LESS", "FRX");
+        }
+        let mut child = command.stdin(Stdio::piped()).spawn()?;
+        let _sigint = IgnoreSigint::new();
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        // the pager may be quit before it reads it all
+        let _ = writeln!(stdin, "{}", body);
+        drop(stdin);
+        child.wait()?;
+        Ok(())
+    }
+}
+
+/// Lines `body` takes on a terminal `columns` wide
+fn height(body: &str, columns: usize) -> usize {
+    body.lines()
+        .map(|line| display_width(&strip(line, true)).div_ceil(columns).max(1))
+        .sum()
+}
+
+/// `s` without its OSC escape sequences, such as hyperlinks, and without its
+/// CSI ones, such as colors, if `csi`
+fn strip(s: &str, csi: bool) -> String {
+    let mut text = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI, up to its final byte
+            Some('[') if csi => {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC, up to BEL or ST
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            Some(c) if !csi => {
+                text.push('\x1b');
+                text.push(c);
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+impl Formatter for Paged<'_> {
+    fn style(&self, element: Element, s: &str) -> String {
+        self.inner.style(element, s)
+    }
+
+    fn explain(&self, entry: &Entry) -> String {
+        self.inner.explain(entry)
+    }
+
+    fn translation(&self, translation: &Translation) -> String {
+        self.inner.translation(translation)
+    }
+
+    fn error(&self, message: &str) -> String {
+        self.inner.error(message)
+    }
+
+    fn no_result(&self) -> String {
+        self.inner.no_result()
+    }
+
+    fn link(&self, s: &str, word: &str) -> String {
+        self.inner.link(s, word)
+    }
+
+    fn width(&self) -> Option<usize> {
+        self.inner.width()
+    }
+
+    fn print(&mut self, word: &str, body: &str) {
+        let tall = match terminal_size() {
+            // keep a line for the prompt
+            Some((Width(w), Height(h))) => height(body, w.max(1) as usize) >= h as usize,
+            None => false,
+        };
+        if !tall {
+            return self.inner.print(word, body);
+        }
+        // pagers may show the escapes of hyperlinks as text
+        let paged = if self.links {
+            body.to_owned()
+        } else {
+            strip(body, false)
+        };
+        if let Err(err) = self.page(&paged) {
+            log::warn!("cannot run pager '{}': {}", self.command, err);
+            self.inner.print(word, body);
+        }
+    }
+}
+
+/// SIGINT ignored while alive, so that ^C in the pager leaves ydcv and its
+/// prompt alone
+struct IgnoreSigint {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+impl IgnoreSigint {
+    fn new() -> IgnoreSigint {
+        IgnoreSigint {
+            #[cfg(unix)]
+            // SAFETY: SIG_IGN is a valid disposition, restored on drop
+            previous: unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) },
+        }
+    }
+}
+
+impl Drop for IgnoreSigint {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: `previous` was the disposition of SIGINT
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_height() {
+        let body = "\x1b[4mFelix\x1b[0m ['fi:liks]\n\n\x1b]8;;https://example.com\x1b\\费利克斯\x1b]8;;\x1b\\";
+        assert_eq!(
+            "Felix ['fi:liks]",
+            strip(body.lines().next().unwrap(), true)
+        );
+        assert_eq!("费利克斯", strip(body.lines().nth(2).unwrap(), true));
+        assert_eq!(
+            "\x1b[4mFelix\x1b[0m ['fi:liks]\n\n费利克斯",
+            strip(body, false)
+        );
+        assert_eq!(3, height(body, 80));
+        assert_eq!(7, height(body, 4));
+    }
+}
@@ -0,0 +1,326 @@
+//! retries with backoff and a per-backend circuit breaker for HTTP lookups
+
+use crate::ydclient::BoxError;
+use log::debug;
+use once_cell::sync::{Lazy, OnceCell};
+use rand::{thread_rng, Rng};
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use reqwest::{RequestBuilder, Response};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often and how patiently a request is retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// retries after the first attempt
+    pub retries: u32,
+    /// delay before the first retry, doubled for every further one
+    pub base_delay: Duration,
+    /// upper bound of a single delay, including `Retry-After` hints
+    pub max_delay: Duration,
+    /// consecutive failed lookups after which a backend is skipped
+    pub breaker_threshold: u32,
+    /// how long a tripped backend is skipped before it is tried again
+    pub breaker_cooldown: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: 2,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(4),
+            breaker_threshold: 3,
+            breaker_cooldown: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter: a random delay in the upper half of
+    /// `base_delay * 2^attempt`, capped at `max_delay`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+            .as_millis() as u64;
+        Duration::from_millis(thread_rng().gen_range(delay / 2..=delay))
+    }
+}
+
+static POLICY: OnceCell<RetryPolicy> = OnceCell::new();
+
+static BREAKER: Lazy<CircuitBreaker> = Lazy::new(CircuitBreaker::default);
+
+/// Set the retry policy for this session; only the first call has an effect
+pub fn set_policy(policy: RetryPolicy) {
+    let _ = POLICY.set(policy);
+}
+
+fn policy() -> &'static RetryPolicy {
+    POLICY.get_or_init(RetryPolicy::default)
+}
+
+/// Failures of `send` besides those of the HTTP client
+#[derive(Debug)]
+pub enum RetryErr {
+    CircuitOpen(String),
+    /// the backend sent nothing for the read timeout
+    ReadTimeout(String),
+}
+
+impl fmt::Display for RetryErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryErr::CircuitOpen(backend) => write!(
+                f,
+                "Skipping the {} backend after repeated failures in this session",
+                backend
+            ),
+            RetryErr::ReadTimeout(backend) => {
+                write!(f, "The {} backend stopped answering", backend)
+            }
+        }
+    }
+}
+
+impl Error for RetryErr {}
+
+/// Counts consecutive failures of each backend and skips the ones that keep failing
+#[derive(Default)]
+struct CircuitBreaker {
+    state: Mutex<HashMap<String, (u32, Option<Instant>)>>,
+}
+
+impl CircuitBreaker {
+    /// Whether `backend` may be tried; a tripped backend gets one trial after the cooldown
+    fn allow(&self, backend: &str, policy: &RetryPolicy) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.get_mut(backend) {
+            Some((_, Some(tripped))) if tripped.elapsed() < policy.breaker_cooldown => false,
+            Some((failures, tripped @ Some(_))) => {
+                // half-open: one more failure trips it again right away
+                *failures = policy.breaker_threshold.saturating_sub(1);
+                *tripped = None;
+                true
+            }
+            _ => true,
+        }
+    }
+
+    fn record(&self, backend: &str, ok: bool, policy: &RetryPolicy) {
+        let mut state = self.state.lock().unwrap();
+        let (failures, tripped) = state.entry(backend.to_owned()).or_insert((0, None));
+        if ok {
+            *failures = 0;
+        } else {
+            *failures += 1;
+            if *failures >= policy.breaker_threshold {
+                debug!("circuit breaker tripped for {}", backend);
+                *tripped = Some(Instant::now());
+            }
+        }
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Timeouts, refused connections and connections reset by the peer, but not
+/// errors that would happen again, such as an invalid URL
+fn is_transient_error(err: &reqwest::Error) -> bool {
+    if err.is_timeout() || err.is_connect() {
+        return true;
+    }
+    let mut source = err.source();
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            return matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            );
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Delay requested by the server through a `Retry-After: <seconds>` header
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+async_api! {
+    /// Send the request built by `request`, retrying transient failures
+    ///
+    /// 5xx and 429 responses, timeouts and broken connections are retried with
+    /// exponential backoff, as is an answer that does not start within
+    /// `answer_timeout`. A backend that keeps failing is skipped for the rest
+    /// of the cooldown, so that a lookup falls through to the next backend
+    /// instead of waiting for timeouts again.
+    pub async fn send<F>(
+        backend: &str,
+        answer_timeout: Duration,
+        request: F,
+    ) -> Result<Response, BoxError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let policy = policy();
+        if !BREAKER.allow(backend, policy) {
+            return Err(Box::new(RetryErr::CircuitOpen(backend.to_owned())));
+        }
+
+        let mut attempt = 0;
+        loop {
+            // `None` when the answer did not start in time
+            let result = tokio::time::timeout(answer_timeout, request().send())
+                .await
+                .ok();
+
+            let transient = match &result {
+                Some(Ok(response)) => is_transient_status(response.status()),
+                Some(Err(err)) => is_transient_error(err),
+                None => true,
+            };
+            if transient && attempt < policy.retries {
+                let delay = match &result {
+                    Some(Ok(response)) => {
+                        retry_after(response).map(|d| d.min(policy.max_delay))
+                    }
+                    _ => None,
+                }
+                .unwrap_or_else(|| policy.backoff(attempt));
+                debug!(
+                    "{} request failed ({}), retrying in {:?}",
+                    backend,
+                    match &result {
+                        Some(Ok(response)) => response.status().to_string(),
+                        Some(Err(err)) => err.to_string(),
+                        None => "no answer".to_owned(),
+                    },
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+
+            let result = match result {
+                Some(Ok(response)) if transient => {
+                    response.error_for_status().map_err(Into::into)
+                }
+                Some(Ok(response)) => Ok(response),
+                Some(Err(err)) => Err(err.into()),
+                None => Err(Box::new(RetryErr::ReadTimeout(backend.to_owned())) as BoxError),
+            };
+            BREAKER.record(backend, result.is_ok(), policy);
+            return result;
+        }
+    }
+}
+
+/// Body of `response`, failing when none of it arrives for `read_timeout`
+pub(crate) async fn read(
+    backend: &str,
+    read_timeout: Duration,
+    mut response: Response,
+) -> Result<String, BoxError> {
+    let mut body = vec![];
+    loop {
+        let chunk = tokio::time::timeout(read_timeout, response.chunk())
+            .await
+            .map_err(|_| RetryErr::ReadTimeout(backend.to_owned()))??;
+        match chunk {
+            Some(chunk) => body.extend_from_slice(&chunk),
+            None => return Ok(String::from_utf8_lossy(&body).into_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nonblocking::block_on;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_backoff_bounds() {
+        let policy = RetryPolicy::default();
+        for attempt in 0..10 {
+            let delay = policy.backoff(attempt);
+            let cap = (policy.base_delay * 2u32.pow(attempt)).min(policy.max_delay);
+            assert!(delay >= cap / 2 && delay <= cap, "{:?} {:?}", delay, cap);
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let policy = RetryPolicy {
+            breaker_threshold: 2,
+            breaker_cooldown: Duration::from_millis(50),
+            ..RetryPolicy::default()
+        };
+        let breaker = CircuitBreaker::default();
+
+        breaker.record("scraper", false, &policy);
+        assert!(breaker.allow("scraper", &policy));
+        breaker.record("scraper", false, &policy);
+        assert!(!breaker.allow("scraper", &policy));
+        assert!(breaker.allow("openapi", &policy));
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow("scraper", &policy));
+        breaker.record("scraper", false, &policy);
+        assert!(!breaker.allow("scraper", &policy));
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.read_exact(&mut [0; 16]).unwrap();
+            // half of the body, then silence
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")
+                .unwrap();
+            thread::sleep(Duration::from_secs(2));
+        });
+
+        let err = block_on(async {
+            let client = reqwest::Client::builder().no_proxy().build().unwrap();
+            let response = client.get(&url).send().await.unwrap();
+            read("test", Duration::from_millis(100), response).await
+        })
+        .unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(RetryErr::ReadTimeout(_))));
+    }
+
+    #[test]
+    fn test_invalid_url_is_permanent() {
+        let err = block_on(reqwest::Client::new().get("http://").send()).unwrap_err();
+        assert!(!is_transient_error(&err));
+    }
+}
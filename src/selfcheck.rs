@@ -0,0 +1,125 @@
+//! self-check of the scraping rules against live pages or saved fixtures
+
+use scraper::Html;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use ydcv::backend::Backends;
+use ydcv::extract::{RuleCheck, ScraperRules};
+use ydcv::lang::{detect, Lang};
+use ydcv::ydclient::lookup_word;
+
+/// Words whose result pages have every section the rules extract
+const CANARIES: &[&str] = &["hello", "暂时"];
+
+#[derive(StructOpt)]
+pub struct SelfcheckOptions {
+    #[structopt(
+        long = "fixtures",
+        value_name = "DIR",
+        help = "replay <DIR>/<word>.html instead of fetching the pages"
+    )]
+    fixtures: Option<PathBuf>,
+
+    #[structopt(
+        long = "save",
+        value_name = "DIR",
+        help = "save the fetched pages as <DIR>/<word>.html for later replay"
+    )]
+    save: Option<PathBuf>,
+
+    #[structopt(value_name = "WORDS", help = "canary words (default: hello 暂时)")]
+    words: Vec<String>,
+}
+
+/// Rules of a page that matched nothing, or why the page could not be checked
+fn check_page(rules: &ScraperRules, word: &str, body: &str) -> Result<Vec<RuleCheck>, String> {
+    let html = Html::parse_document(body);
+    if rules.is_no_data(&html) {
+        return Err("the page says the word was not found".to_owned());
+    }
+    Ok(rules
+        .check(&html, detect(word).is_chinese())
+        .into_iter()
+        .filter(|check| check.matches == 0)
+        .collect())
+}
+
+fn fixture(dir: &Path, word: &str) -> PathBuf {
+    dir.join(format!("{}.html", word))
+}
+
+/// Run `ydcv selfcheck`, returning whether every canary page passed
+pub fn run(opts: &SelfcheckOptions, client: &Backends) -> Result<bool, Box<dyn Error>> {
+    let words = if opts.words.is_empty() {
+        CANARIES.iter().map(|w| w.to_string()).collect()
+    } else {
+        opts.words.clone()
+    };
+
+    let mut ok = true;
+    for word in &words {
+        let body = match &opts.fixtures {
+            Some(dir) => {
+                let path = fixture(dir, word);
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            None => lookup_word(word, Lang::Auto, Lang::Auto, &client.scraper)
+                .map_err(|e| e.to_string()),
+        };
+        if let (Ok(body), Some(dir)) = (&body, &opts.save) {
+            fs::create_dir_all(dir)?;
+            fs::write(fixture(dir, word), body)?;
+        }
+
+        match body.and_then(|body| check_page(&client.rules, word, &body)) {
+            Ok(empty) if empty.is_empty() => println!("ok    {}", word),
+            Ok(empty) => {
+                ok = false;
+                println!("FAIL  {}", word);
+                for check in empty {
+                    println!("      {} matched nothing: {}", check.rule, check.selector);
+                }
+            }
+            Err(err) => {
+                ok = false;
+                println!("FAIL  {}: {}", word, err);
+            }
+        }
+    }
+
+    Ok(ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_page() {
+        let rules = ScraperRules::embedded();
+        let page = r#"
+        <div class="phone_con"><div class="per-phone">
+            <span>英</span><span class="phonetic">/ həˈləʊ /</span>
+        </div></div>
+        <div class="basic">
+            <ul class="word-exp"><span class="pos">int.</span><span class="trans">喂，你好</span></ul>
+        </div>
+        "#;
+
+        let empty = check_page(&rules, "hello", page).unwrap();
+        assert_eq!(
+            vec!["web_keys", "web_values"],
+            empty.iter().map(|c| c.rule).collect::<Vec<_>>()
+        );
+        assert_eq!(".web_trans .col2 .point", empty[0].selector);
+
+        assert!(check_page(
+            &rules,
+            "hello",
+            r#"<div class="no-data-prompt">no result</div>"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! local HTTP server answering lookups with JSON or HTML
+
+use reqwest::Url;
+use serde_json::json;
+use std::error::Error;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+use tiny_http::{Header, Method, Request, Response, Server};
+use ydcv::backend::Backends;
+use ydcv::cache::Cache;
+use ydcv::formatters::ArticleFormatter;
+use ydcv::lang::Lang;
+use ydcv::retry::RetryErr;
+use ydcv::ydclient::{YdClient, YdClientErr};
+
+/// Cache settings of the long-running modes
+#[derive(StructOpt)]
+pub struct CacheOptions {
+    #[structopt(
+        long = "cache-size",
+        help = "number of lookup results kept in memory",
+        default_value = "1000"
+    )]
+    cache_size: usize,
+
+    #[structopt(
+        long = "cache-ttl",
+        help = "seconds a lookup result is kept in memory",
+        default_value = "3600"
+    )]
+    cache_ttl: u64,
+}
+
+impl CacheOptions {
+    pub fn cache(&self) -> Cache {
+        Cache::new(self.cache_size, Duration::from_secs(self.cache_ttl))
+    }
+}
+
+#[derive(StructOpt)]
+pub struct ServeOptions {
+    #[structopt(
+        long = "listen",
+        value_name = "ADDR",
+        help = "address to listen on",
+        default_value = "127.0.0.1:8765"
+    )]
+    listen: String,
+
+    #[structopt(
+        short = "j",
+        long = "jobs",
+        help = "number of requests handled at once",
+        default_value = "4"
+    )]
+    jobs: usize,
+
+    #[structopt(flatten)]
+    cache: CacheOptions,
+}
+
+/// Output format of `/lookup`
+#[derive(Debug, PartialEq)]
+enum Format {
+    Json,
+    Html,
+}
+
+/// Parameters of a `/lookup` request
+#[derive(Debug, PartialEq)]
+struct Query {
+    q: String,
+    from: Lang,
+    to: Lang,
+    format: Format,
+}
+
+impl Query {
+    fn parse(url: &Url) -> Result<Query, String> {
+        let mut query = Query {
+            q: String::new(),
+            from: Lang::Auto,
+            to: Lang::Auto,
+            format: Format::Json,
+        };
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "q" => query.q = value.trim().to_owned(),
+                "from" => query.from = value.parse()?,
+                "to" => query.to = value.parse()?,
+                "format" => {
+                    query.format = match value.as_ref() {
+                        "json" => Format::Json,
+                        "html" => Format::Html,
+                        _ => {
+                            return Err(format!(
+                                "unknown format '{}', expected json or html",
+                                value
+                            ))
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if query.q.is_empty() {
+            return Err("missing query parameter q".to_owned());
+        }
+        Ok(query)
+    }
+}
+
+/// HTTP status for a failed lookup
+fn status_of(err: &(dyn Error + 'static)) -> u16 {
+    if let Some(err) = err.downcast_ref::<YdClientErr>() {
+        return match err {
+            // the scraper is asked first, so its error tells more, unless it
+            // cannot serve the language pair at all
+            YdClientErr::NewAndOldAPIError(new_api_err, old_api_err) => {
+                match old_api_err.downcast_ref::<YdClientErr>() {
+                    Some(YdClientErr::UnsupportedLangPair(..)) => {
+                        match new_api_err.downcast_ref::<YdClientErr>() {
+                            Some(YdClientErr::NewApiValueError) => 400,
+                            _ => status_of(new_api_err.as_ref()),
+                        }
+                    }
+                    _ => status_of(old_api_err.as_ref()),
+                }
+            }
+            YdClientErr::UnsupportedLangPair(..) => 400,
+            YdClientErr::NewApiValueError | YdClientErr::Cancelled => 503,
+            YdClientErr::ApiError(_) => 502,
+        };
+    }
+    if err.is::<RetryErr>() {
+        return 503;
+    }
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(err) if err.is_timeout() => 504,
+        Some(_) => 502,
+        None => 500,
+    }
+}
+
+/// Status, content type and body of the answer to `GET url`
+fn answer(url: &str, client: &mut Backends, cache: &Cache) -> (u16, &'static str, String) {
+    const JSON: &str = "application/json; charset=utf-8";
+    let error = |status, message: String| (status, JSON, json!({ "error": message }).to_string());
+
+    let url = match Url::parse("http://localhost").and_then(|base| base.join(url)) {
+        Ok(url) if url.path() == "/lookup" => url,
+        _ => return error(404, "not found".to_owned()),
+    };
+    let query = match Query::parse(&url) {
+        Ok(query) => query,
+        Err(err) => return error(400, err),
+    };
+
+    let result = cache.get_or_lookup(&query.q, query.from, query.to, || {
+        client.lookup_word_lang(&query.q, query.from, query.to, false)
+    });
+    match result {
+        Ok(response) => {
+            let status = if response.is_ok() { 200 } else { 404 };
+            match query.format {
+                Format::Json => (status, JSON, serde_json::to_string(&response).unwrap()),
+                Format::Html => match ArticleFormatter::new(true).article(&response) {
+                    Some(article) => (status, "text/html; charset=utf-8", article),
+                    None => (404, "text/html; charset=utf-8", String::new()),
+                },
+            }
+        }
+        Err(err) => error(status_of(err.as_ref()), err.to_string()),
+    }
+}
+
+fn handle(request: Request, client: &mut Backends, cache: &Cache) {
+    let (status, content_type, body) = if *request.method() == Method::Get {
+        answer(request.url(), client, cache)
+    } else {
+        (405, "text/plain", "only GET is supported".to_owned())
+    };
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", content_type).unwrap());
+    if let Err(err) = request.respond(response) {
+        log::debug!("cannot send the response: {}", err);
+    }
+}
+
+/// Run `ydcv serve` until the process is killed
+pub fn run(opts: &ServeOptions, client: &Backends) -> Result<(), Box<dyn Error>> {
+    if opts.jobs == 0 {
+        return Err("--jobs must be positive".into());
+    }
+
+    let server =
+        Arc::new(Server::http(&opts.listen).map_err(|e| format!("{}: {}", opts.listen, e))?);
+    let cache = Arc::new(opts.cache.cache());
+    eprintln!("ydcv: listening on http://{}", server.server_addr());
+
+    let workers = (0..opts.jobs)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let cache = Arc::clone(&cache);
+            let mut client = client.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &mut client, &cache);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ydcv::ydresponse::YdResponse;
+
+    #[test]
+    fn test_parse_query() {
+        let url = Url::parse("http://localhost/lookup?q=%E4%BD%A0%E5%A5%BD&to=ja&format=html");
+        assert_eq!(
+            Ok(Query {
+                q: "你好".to_owned(),
+                from: Lang::Auto,
+                to: Lang::Ja,
+                format: Format::Html,
+            }),
+            Query::parse(&url.unwrap())
+        );
+
+        let url = Url::parse("http://localhost/lookup?q=hello&format=xml").unwrap();
+        assert!(Query::parse(&url).is_err());
+        let url = Url::parse("http://localhost/lookup?from=en").unwrap();
+        assert!(Query::parse(&url).is_err());
+    }
+
+    #[test]
+    fn test_status_of() {
+        let unsupported = YdClientErr::UnsupportedLangPair(Lang::En, Lang::Ja);
+        assert_eq!(400, status_of(&unsupported));
+        assert_eq!(
+            400,
+            status_of(&YdClientErr::NewAndOldAPIError(
+                Box::new(YdClientErr::NewApiValueError),
+                Box::new(unsupported),
+            ))
+        );
+        assert_eq!(
+            503,
+            status_of(&YdClientErr::NewAndOldAPIError(
+                Box::new(YdClientErr::NewApiValueError),
+                Box::new(RetryErr::CircuitOpen("scraper".to_owned())),
+            ))
+        );
+        assert_eq!(502, status_of(&YdClientErr::ApiError("108".to_owned())));
+    }
+
+    #[test]
+    fn test_answer_from_cache() {
+        let mut client = Backends::with_client(reqwest::Client::new());
+        let cache = Cache::new(10, Duration::from_secs(60));
+        let response = YdResponse::new_raw(
+            r#"{"query":"hello","errorCode":0,"translation":["你好"]}"#.to_owned(),
+        )
+        .unwrap();
+        cache.insert("hello", Lang::Auto, Lang::Auto, response);
+
+        let (status, content_type, body) = answer("/lookup?q=hello", &mut client, &cache);
+        assert_eq!(200, status);
+        assert!(content_type.starts_with("application/json"));
+        assert!(body.contains(r#""translation":["你好"]"#), "{}", body);
+
+        let (status, _, body) = answer("/lookup?q=hello&format=html", &mut client, &cache);
+        assert_eq!(200, status);
+        assert!(body.starts_with("<style>"), "{}", body);
+        assert!(body.contains(r#"<span class="ydcv-headword">hello</span>"#));
+
+        let response = YdResponse::new_raw(r#"{"query":"xyzzy","errorCode":0}"#.to_owned());
+        cache.insert("xyzzy", Lang::Auto, Lang::Auto, response.unwrap());
+        let (status, content_type, body) =
+            answer("/lookup?q=xyzzy&format=html", &mut client, &cache);
+        assert_eq!(404, status);
+        assert!(content_type.starts_with("text/html"));
+        assert_eq!("", body);
+
+        assert_eq!(404, answer("/other", &mut client, &cache).0);
+        assert_eq!(400, answer("/lookup?q=", &mut client, &cache).0);
+    }
+}
@@ -0,0 +1,226 @@
+//! output laid out by templates
+//!
+//! Templates use the Jinja syntax of [minijinja] and are rendered from the
+//! `Entry` of a lookup, with the same fields as the `--json` output:
+//! `headword`, `phonetic` (a string, or `uk` and `us`), `translation`,
+//! `senses` (`pos` and `text`), `web` (`key` and `value`) and `definitions`.
+//! Missing fields are empty, so `{{ phonetic.uk }}` needs no guard.
+//!
+//! The built-in templates in `src/templates` are meant as examples.
+//!
+//! [minijinja]: https://docs.rs/minijinja
+
+use crate::config::Config;
+use crate::formatters::{Element, Entry, Formatter};
+use minijinja::{Environment, Error, UndefinedBehavior};
+use std::cell::Cell;
+use std::fs;
+use std::path::PathBuf;
+
+/// Names and sources of the built-in templates
+pub const BUILTIN: [(&str, &str); 3] = [
+    ("oneline", include_str!("templates/oneline.jinja")),
+    ("anki", include_str!("templates/anki.jinja")),
+    ("chat", include_str!("templates/chat.jinja")),
+];
+
+/// Formatter rendering a template
+pub struct TemplateFormatter {
+    env: Environment<'static>,
+    name: String,
+    /// whether the body to print is the error of the last rendering
+    failed: Cell<bool>,
+    errors: Cell<usize>,
+}
+
+impl TemplateFormatter {
+    /// Compile `source`, called `name` in the errors
+    pub fn new(name: &str, source: String) -> Result<TemplateFormatter, String> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Chainable);
+        env.add_template_owned(name.to_owned(), source.clone())
+            .map_err(|e| describe(&e, &source))?;
+        Ok(TemplateFormatter {
+            env,
+            name: name.to_owned(),
+            failed: Cell::new(false),
+            errors: Cell::new(0),
+        })
+    }
+
+    /// The built-in template `name`, else `name.jinja` in the `templates`
+    /// directory next to the configuration file, else the file `name`
+    pub fn load(name: &str, config: &Config) -> Result<TemplateFormatter, String> {
+        if let Some((_, source)) = BUILTIN.iter().find(|(n, _)| *n == name) {
+            return TemplateFormatter::new(name, (*source).to_owned());
+        }
+        let path = config
+            .dir
+            .as_ref()
+            .map(|d| d.join("templates").join(format!("{}.jinja", name)))
+            .filter(|p| p.is_file())
+            .unwrap_or_else(|| PathBuf::from(name));
+        let source = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "template '{}': {} (built-in ones: {})",
+                path.display(),
+                e,
+                BUILTIN.map(|(n, _)| n).join(", ")
+            )
+        })?;
+        TemplateFormatter::new(&path.display().to_string(), source)
+    }
+
+    pub fn render(&self, entry: &Entry) -> Result<String, String> {
+        let template = self.env.get_template(&self.name).expect("added by new");
+        template
+            .render(entry)
+            .map_err(|e| describe(&e, template.source()))
+    }
+
+    /// Number of entries the template failed to render
+    pub fn errors(&self) -> usize {
+        self.errors.get()
+    }
+}
+
+/// `error`, followed by the line of `source` it comes from
+fn describe(error: &Error, source: &str) -> String {
+    let mut message = match error.detail() {
+        Some(detail) => format!("{}: {}", error.kind(), detail),
+        None => error.kind().to_string(),
+    };
+    if let (Some(name), Some(line)) = (error.name(), error.line()) {
+        message = format!("template '{}', line {}: {}", name, line, message);
+        if let Some(text) = source.lines().nth(line - 1) {
+            message.push_str(&format!("\n{:>5} | {}", line, text));
+            // underline the failing part when it is on that line
+            let start = source.lines().take(line - 1).map(|l| l.len() + 1).sum();
+            if let Some(range) = error.range().filter(|r| r.start >= start) {
+                let before = text.get(..range.start - start).unwrap_or(text);
+                let span = text
+                    .get(range.start - start..range.end - start)
+                    .unwrap_or("");
+                message.push_str(&format!(
+                    "\n      | {}{}",
+                    " ".repeat(before.chars().count()),
+                    "^".repeat(span.chars().count().max(1))
+                ));
+            }
+        }
+    }
+    message
+}
+
+impl Formatter for TemplateFormatter {
+    fn style(&self, _: Element, s: &str) -> String {
+        s.to_owned()
+    }
+
+    fn explain(&self, entry: &Entry) -> String {
+        self.render(entry).unwrap_or_else(|e| {
+            self.failed.set(true);
+            self.errors.set(self.errors.get() + 1);
+            e
+        })
+    }
+
+    /// Print `body`, or the error of the template to stderr, out of the
+    /// way of the output
+    fn print(&mut self, _: &str, body: &str) {
+        if self.failed.replace(false) {
+            eprintln!("ydcv: {}", body);
+        } else {
+            println!("{}", body);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ydresponse::YdResponse;
+
+    static RAW_FELIX: &str = r#"
+    {
+        "translation":["费利克斯"],
+        "basic":{
+            "us_phonetic":"'fi:liks",
+            "uk_phonetic":"'fi:liks",
+            "explains":["n. 菲力克斯（男子名）；费力克斯制导炸弹"]
+        },
+        "query":"Felix",
+        "errorCode":0,
+        "web":[
+            {"value":["费利克斯","费利斯","菲力克斯"],"key":"Felix"},
+            {"value":["菲利克斯·马加特","马加特"],"key":"Felix Magath"}
+        ]
+    }"#;
+
+    fn render(name: &str, raw: &str) -> String {
+        let response = YdResponse::new_raw(raw.to_owned()).unwrap();
+        TemplateFormatter::load(name, &Config::default())
+            .unwrap()
+            .render(&Entry::new(&response).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_builtin() {
+        assert_eq!("Felix ['fi:liks] 费利克斯", render("oneline", RAW_FELIX));
+        assert_eq!(
+            "Felix\tUK [&#x27;fi:liks] US [&#x27;fi:liks]<br>费利克斯<br>\
+             <i>n.</i> 菲力克斯（男子名）；费力克斯制导炸弹<br>\
+             <b>Felix</b>: 费利克斯; 费利斯; 菲力克斯<br>\
+             <b>Felix Magath</b>: 菲利克斯·马加特; 马加特<br>",
+            render("anki", RAW_FELIX)
+        );
+        assert_eq!(
+            "*Felix* UK /'fi:liks/ US /'fi:liks/ 费利克斯
+• _n._ 菲力克斯（男子名）；费力克斯制导炸弹
+
+> Felix: 费利克斯; 费利斯; 菲力克斯
+> Felix Magath: 菲利克斯·马加特; 马加特
+",
+            render("chat", RAW_FELIX)
+        );
+
+        let bare =
+            r#"{"query":"hi","errorCode":0,"basic":{"phonetic":"haɪ","explains":["int. 嗨"]}}"#;
+        assert_eq!("hi [haɪ] 嗨", render("oneline", bare));
+        assert_eq!("hi\t[haɪ]<br><i>int.</i> 嗨<br>", render("anki", bare));
+        assert_eq!("*hi* /haɪ/\n• _int._ 嗨", render("chat", bare));
+
+        let markup = r#"{"query":"<b>","errorCode":0,"translation":["粗体"]}"#;
+        assert_eq!("&lt;b&gt;\t粗体<br>", render("anki", markup));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = TemplateFormatter::new("mine", "{{ headword }}\n{% if %}\n".to_owned())
+            .err()
+            .unwrap();
+        assert!(
+            err.starts_with("template 'mine', line 2: syntax error"),
+            "{}",
+            err
+        );
+        assert!(err.contains("\n    2 | {% if %}"), "{}", err);
+
+        let fmt = TemplateFormatter::new("mine", "{{ headword }}\n  {{ senses|nope }}".to_owned())
+            .unwrap();
+        let response = YdResponse::new_raw(RAW_FELIX.to_owned()).unwrap();
+        let err = fmt.render(&Entry::new(&response).unwrap()).unwrap_err();
+        assert_eq!(0, fmt.errors());
+        assert_eq!(err, fmt.explain(&Entry::new(&response).unwrap()));
+        assert_eq!(1, fmt.errors());
+        assert_eq!(
+            "template 'mine', line 2: unknown filter: filter nope is unknown
+    2 |   {{ senses|nope }}
+      |             ^^^^",
+            err
+        );
+
+        assert!(TemplateFormatter::load("/no/such/template", &Config::default()).is_err());
+    }
+}
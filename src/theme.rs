@@ -0,0 +1,400 @@
+//! color themes of the terminal output
+//!
+//! A theme gives a `Style` to each `Element`. Styles are written like
+//! `"bold underline"`, `"yellow"`, `"italic 245"` or `"#d7875f on 236"`: any of
+//! `bold`, `dim`, `italic`, `underline` and `reverse`, then a foreground color
+//! and `on` a background color. Colors are one of the 16 names (`red`,
+//! `bright-red`, ...), a 256-color index or a `#rrggbb` truecolor.
+
+use crate::config::Config;
+use crate::formatters::Element;
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+
+/// Names of the built-in themes
+pub const BUILTIN: [&str; 3] = ["dark", "light", "high-contrast"];
+
+/// Names of the 16 colors, in the order of their SGR codes
+const NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// one of the 16 colors, `8..16` being the bright ones
+    Ansi(u8),
+    Fixed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Color, String> {
+        if let Some(hex) = s.strip_prefix('#') {
+            let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("-"), 16);
+            return match (hex.len(), channel(0), channel(2), channel(4)) {
+                (6, Ok(r), Ok(g), Ok(b)) => Ok(Color::Rgb(r, g, b)),
+                _ => Err(format!("invalid color '{}', expected #rrggbb", s)),
+            };
+        }
+        if let Ok(index) = s.parse::<u8>() {
+            return Ok(Color::Fixed(index));
+        }
+        let (bright, name) = match s.strip_prefix("bright-") {
+            Some(name) => (8, name),
+            None => (0, s),
+        };
+        match NAMES.iter().position(|n| *n == name) {
+            Some(code) => Ok(Color::Ansi(bright + code as u8)),
+            None => Err(format!(
+                "unknown color '{}', expected a name such as red or bright-red, 0-255 or #rrggbb",
+                s
+            )),
+        }
+    }
+}
+
+impl Color {
+    /// SGR parameters of the color as a foreground (`base` 30) or a
+    /// background (`base` 40)
+    fn sgr(self, base: u8, truecolor: bool) -> String {
+        match self {
+            Color::Ansi(code) if code < 8 => (base + code).to_string(),
+            Color::Ansi(code) => (base + 60 + code - 8).to_string(),
+            Color::Fixed(index) => format!("{};5;{}", base + 8, index),
+            Color::Rgb(r, g, b) if truecolor => format!("{};2;{};{};{}", base + 8, r, g, b),
+            Color::Rgb(r, g, b) => format!("{};5;{}", base + 8, to_fixed(r, g, b)),
+        }
+    }
+}
+
+/// The nearest color of the 6x6x6 cube of the 256 colors, whose levels
+/// are 0, 95, 135, 175, 215 and 255
+fn to_fixed(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8| match c {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (c as u16 - 35) / 40,
+    };
+    (16 + 36 * level(r) + 6 * level(g) + level(b)) as u8
+}
+
+/// How an element looks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Style, String> {
+        let mut style = Style::default();
+        let mut words = s.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "bold" => style.bold = true,
+                "dim" => style.dim = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "reverse" => style.reverse = true,
+                "none" => {}
+                "on" => {
+                    let color = words.next().ok_or("missing color after 'on'")?;
+                    style.bg = Some(color.parse()?);
+                }
+                _ => style.fg = Some(word.parse()?),
+            }
+        }
+        Ok(style)
+    }
+}
+
+impl Style {
+    /// SGR parameters, or `None` for the default look
+    pub fn sgr(&self, truecolor: bool) -> Option<String> {
+        let mut params = vec![];
+        for (on, code) in [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+            (self.reverse, "7"),
+        ] {
+            if on {
+                params.push(code.to_owned());
+            }
+        }
+        params.extend(self.fg.map(|c| c.sgr(30, truecolor)));
+        params.extend(self.bg.map(|c| c.sgr(40, truecolor)));
+        (!params.is_empty()).then(|| params.join(";"))
+    }
+}
+
+/// Styles of the elements; those left out have the default look
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub name: String,
+    styles: HashMap<Element, Style>,
+}
+
+impl Theme {
+    fn from_styles(name: &str, styles: &[(Element, &str)]) -> Theme {
+        Theme {
+            name: name.to_owned(),
+            styles: styles
+                .iter()
+                .map(|(element, style)| (*element, style.parse().expect("built-in style")))
+                .collect(),
+        }
+    }
+
+    /// The 16 colors of the original ydcv, for dark backgrounds
+    pub fn dark() -> Theme {
+        Theme::from_styles(
+            "dark",
+            &[
+                (Element::Headword, "underline"),
+                (Element::Phonetic, "yellow"),
+                (Element::SectionHeader, "cyan"),
+                (Element::WebKey, "yellow"),
+                (Element::WebValue, "magenta"),
+                (Element::Error, "red"),
+            ],
+        )
+    }
+
+    /// Darker 256 colors that stay readable on light backgrounds
+    pub fn light() -> Theme {
+        Theme::from_styles(
+            "light",
+            &[
+                (Element::Headword, "bold underline"),
+                (Element::Phonetic, "130"),
+                (Element::Pos, "italic 242"),
+                (Element::SectionHeader, "bold 25"),
+                (Element::WebKey, "94"),
+                (Element::WebValue, "90"),
+                (Element::Error, "160"),
+            ],
+        )
+    }
+
+    /// Bold bright colors
+    pub fn high_contrast() -> Theme {
+        Theme::from_styles(
+            "high-contrast",
+            &[
+                (Element::Headword, "bold underline bright-white"),
+                (Element::Phonetic, "bold bright-yellow"),
+                (Element::Pos, "bold bright-cyan"),
+                (Element::SectionHeader, "bold reverse"),
+                (Element::WebKey, "bold bright-yellow"),
+                (Element::WebValue, "bright-magenta"),
+                (Element::Error, "bold bright-white on red"),
+            ],
+        )
+    }
+
+    pub fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            _ => None,
+        }
+    }
+
+    /// The theme called `name` in `config`, or built in
+    pub fn load(name: &str, config: &Config) -> Result<Theme, String> {
+        Theme::load_nested(name, config, 0)
+    }
+
+    fn load_nested(name: &str, config: &Config, depth: usize) -> Result<Theme, String> {
+        let Some(user) = config.themes.get(name) else {
+            return Theme::builtin(name).ok_or_else(|| {
+                format!(
+                    "unknown theme '{}', expected one of: {}",
+                    name,
+                    Theme::names(config).join(", ")
+                )
+            });
+        };
+        // a theme based on itself, directly or not, would never end
+        if depth > config.themes.len() {
+            return Err(format!("theme '{}' is based on itself", name));
+        }
+        let mut theme = match user.base.as_deref() {
+            Some(base) if base != name => Theme::load_nested(base, config, depth + 1)?,
+            // a user theme called like a built-in one amends it
+            _ => Theme::builtin(name).unwrap_or_else(Theme::dark),
+        };
+        theme.name = name.to_owned();
+        for (element, style) in &user.styles {
+            let context = |e: String| format!("theme '{}', {}: {}", name, element, e);
+            theme.styles.insert(
+                element.parse().map_err(context)?,
+                style.parse().map_err(context)?,
+            );
+        }
+        Ok(theme)
+    }
+
+    /// Built-in themes, then the user-defined ones that do not replace them
+    pub fn names(config: &Config) -> Vec<String> {
+        let mut names = BUILTIN.map(str::to_owned).to_vec();
+        names.extend(
+            config
+                .themes
+                .keys()
+                .filter(|name| !BUILTIN.contains(&name.as_str()))
+                .cloned(),
+        );
+        names
+    }
+
+    pub fn get(&self, element: Element) -> Style {
+        self.styles.get(&element).copied().unwrap_or_default()
+    }
+}
+
+/// Whether the terminal shows 24-bit colors, as `$COLORTERM` tells
+pub fn has_truecolor() -> bool {
+    matches!(
+        env::var("COLORTERM").as_deref(),
+        Ok("truecolor") | Ok("24bit")
+    )
+}
+
+/// Whether the terminal shows OSC 8 hyperlinks, as far as its environment
+/// tells; `FORCE_HYPERLINK` overrides the guess
+pub fn has_hyperlinks() -> bool {
+    if let Some(force) = env::var_os("FORCE_HYPERLINK") {
+        return !force.is_empty() && force != "0";
+    }
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    if var("TERM") == "dumb" || env::var_os("CI").is_some() {
+        return false;
+    }
+    let vte = var("VTE_VERSION").parse::<u32>().unwrap_or(0);
+    vte >= 5000
+        || env::var_os("WT_SESSION").is_some()
+        || env::var_os("KONSOLE_VERSION").is_some()
+        || env::var_os("DOMTERM").is_some()
+        || matches!(
+            var("TERM_PROGRAM").as_str(),
+            "iTerm.app" | "WezTerm" | "vscode" | "ghostty" | "Hyper"
+        )
+        || matches!(
+            var("TERM").as_str(),
+            "xterm-kitty" | "alacritty" | "foot" | "xterm-ghostty"
+        )
+}
+
+/// Whether to color the output for `--color` `choice`: `auto` follows
+/// `NO_COLOR` and `CLICOLOR_FORCE`, then whether stdout is a terminal
+pub fn use_color(choice: &str, tty: bool) -> bool {
+    let set = |name: &str| env::var_os(name).is_some_and(|v| !v.is_empty() && v != "0");
+    match choice {
+        "always" => true,
+        "never" => false,
+        _ if env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) => false,
+        _ => set("CLICOLOR_FORCE") || tty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ThemeConfig;
+
+    #[test]
+    fn test_parse_style() {
+        let style: Style = "bold italic #d7875f on 236".parse().unwrap();
+        assert_eq!(
+            Style {
+                bold: true,
+                italic: true,
+                fg: Some(Color::Rgb(0xd7, 0x87, 0x5f)),
+                bg: Some(Color::Fixed(236)),
+                ..Style::default()
+            },
+            style
+        );
+        assert_eq!(
+            Some("1;3;38;2;215;135;95;48;5;236".to_owned()),
+            style.sgr(true)
+        );
+        assert_eq!(Some("1;3;38;5;173;48;5;236".to_owned()), style.sgr(false));
+
+        let style: Style = "bright-red on blue".parse().unwrap();
+        assert_eq!(Some("91;44".to_owned()), style.sgr(true));
+        assert_eq!(None, "none".parse::<Style>().unwrap().sgr(true));
+
+        assert!("#12345".parse::<Style>().is_err());
+        assert!("orange".parse::<Style>().is_err());
+        assert!("bold on".parse::<Style>().is_err());
+        assert!("256".parse::<Style>().is_err());
+    }
+
+    #[test]
+    fn test_load_theme() {
+        let mut config = Config::default();
+        config.themes.insert(
+            "mine".to_owned(),
+            ThemeConfig {
+                base: Some("light".to_owned()),
+                styles: [("web-key".to_owned(), "bold 33".to_owned())].into(),
+            },
+        );
+        for (name, base) in [("loop", "again"), ("again", "loop")] {
+            config.themes.insert(
+                name.to_owned(),
+                ThemeConfig {
+                    base: Some(base.to_owned()),
+                    ..ThemeConfig::default()
+                },
+            );
+        }
+        config.themes.insert(
+            "typo".to_owned(),
+            ThemeConfig {
+                styles: [("webkey".to_owned(), "red".to_owned())].into(),
+                ..ThemeConfig::default()
+            },
+        );
+
+        let theme = Theme::load("mine", &config).unwrap();
+        assert_eq!(
+            Some("1;38;5;33".to_owned()),
+            theme.get(Element::WebKey).sgr(true)
+        );
+        assert_eq!(Theme::light().get(Element::Pos), theme.get(Element::Pos));
+        assert_eq!(None, theme.get(Element::Sense).sgr(true));
+
+        assert!(Theme::load("loop", &config).is_err());
+        let err = Theme::load("typo", &config).unwrap_err();
+        assert!(
+            err.starts_with("theme 'typo', webkey: unknown element"),
+            "{}",
+            err
+        );
+        let err = Theme::load("nope", &config).unwrap_err();
+        assert!(
+            err.ends_with("dark, light, high-contrast, again, loop, mine, typo"),
+            "{}",
+            err
+        );
+        for name in BUILTIN {
+            assert_eq!(name, Theme::load(name, &config).unwrap().name);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! preview of the color themes
+
+use structopt::StructOpt;
+use ydcv::config::Config;
+use ydcv::formatters::{AnsiFormatter, Element, Formatter, PlainFormatter};
+use ydcv::theme::Theme;
+use ydcv::ydresponse::YdResponse;
+
+/// Entry shown in every theme, with each element
+const SAMPLE: &str = r#"{
+    "query": "theme",
+    "errorCode": 0,
+    "translation": ["主题"],
+    "basic": {
+        "phonetic": "θiːm",
+        "explains": ["n. 主题；题目；主旋律", "adj. 主题的"]
+    },
+    "web": [{"key": "theme park", "value": ["主题公园", "主题乐园"]}]
+}"#;
+
+#[derive(StructOpt)]
+pub struct ThemesOptions {
+    #[structopt(value_name = "THEMES", help = "themes to show (default: all of them)")]
+    names: Vec<String>,
+}
+
+/// `sample` in `theme`, under the name of the theme; without `color`, only
+/// the layout is shown
+fn preview(theme: Theme, sample: &YdResponse, color: bool) -> String {
+    let name = theme.name.clone();
+    let fmt: Box<dyn Formatter> = if color {
+        Box::new(AnsiFormatter::with_theme(theme))
+    } else {
+        Box::new(PlainFormatter::new(false))
+    };
+    format!(
+        "── {} ──\n{}\n{}\n",
+        name,
+        sample.explain(fmt.as_ref()),
+        fmt.style(Element::Error, "Error looking-up word: network is down")
+    )
+}
+
+/// Run `ydcv themes`, with colors unless `color` is false as for `--color never`
+pub fn run(opts: &ThemesOptions, config: &Config, color: bool) -> Result<(), String> {
+    let names = if opts.names.is_empty() {
+        Theme::names(config)
+    } else {
+        opts.names.clone()
+    };
+    let sample = YdResponse::new_raw(SAMPLE.to_owned()).expect("valid sample");
+    for name in names {
+        println!("{}", preview(Theme::load(&name, config)?, &sample, color));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview() {
+        let sample = YdResponse::new_raw(SAMPLE.to_owned()).unwrap();
+        let shown = preview(Theme::light(), &sample, true);
+        assert!(
+            shown.starts_with("── light ──\n\x1b[1;4mtheme\x1b[0m"),
+            "{}",
+            shown
+        );
+        assert!(
+            shown.contains("     * \x1b[3;38;5;242mn.\x1b[0m 主题"),
+            "{}",
+            shown
+        );
+        assert!(shown.ends_with("\x1b[38;5;160mError looking-up word: network is down\x1b[0m\n"));
+
+        let shown = preview(Theme::light(), &sample, false);
+        assert!(shown.starts_with("── light ──\ntheme"), "{}", shown);
+        assert!(!shown.contains('\x1b'), "{}", shown);
+    }
+}
@@ -0,0 +1,255 @@
+//! splitting and reassembling text for full-text translation
+
+use crate::formatters::Formatter;
+use crate::lang::Lang;
+use serde::Serialize;
+use unicode_width::UnicodeWidthChar;
+
+/// Longest text the openapi translates in a single request, in characters
+pub const MAX_CHUNK_CHARS: usize = 5000;
+
+/// Input longer than this is translated as text instead of looked up as a word
+pub const TRANSLATE_THRESHOLD: usize = 64;
+
+/// Characters that end a sentence
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', ';', '。', '！', '？', '；', '…'];
+
+/// A source paragraph and its translation
+#[derive(Serialize, Debug)]
+pub struct Paragraph {
+    pub source: String,
+    pub translation: String,
+}
+
+/// Full-text translation, one entry per source paragraph
+#[derive(Serialize, Debug)]
+pub struct Translation {
+    pub paragraphs: Vec<Paragraph>,
+}
+
+/// Whether `text` should go to full-text translation rather than the dictionary
+pub fn is_text(text: &str) -> bool {
+    text.contains('\n') || text.chars().count() > TRANSLATE_THRESHOLD
+}
+
+/// Split `text` into paragraphs, each split into chunks of at most `limit` characters
+///
+/// Paragraphs are separated by blank lines, and the lines of a hard-wrapped
+/// paragraph are joined back so that its sentences are translated whole.
+/// Chunks break at sentence boundaries where possible, then at whitespace, and
+/// only split a word when nothing else fits. Blank lines are kept as empty
+/// paragraphs so the translation lines up with the source.
+pub fn split(text: &str, limit: usize) -> Vec<(String, Vec<String>)> {
+    let mut result = vec![];
+    let mut paragraph = String::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            if !paragraph.is_empty() {
+                let chunks = chunk(&paragraph, limit);
+                result.push((std::mem::take(&mut paragraph), chunks));
+            }
+            result.push((String::new(), vec![]));
+            continue;
+        }
+        if let (Some(last), Some(first)) = (paragraph.chars().last(), line.chars().next()) {
+            // wrapped CJK text has no space to put back at the break
+            if !is_wide(last) && !is_wide(first) {
+                paragraph.push(' ');
+            }
+        }
+        paragraph.push_str(line);
+    }
+    if !paragraph.is_empty() {
+        let chunks = chunk(&paragraph, limit);
+        result.push((paragraph, chunks));
+    }
+    result
+}
+
+fn is_wide(c: char) -> bool {
+    c.width().unwrap_or(0) > 1
+}
+
+fn chunk(paragraph: &str, limit: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut current = String::new();
+
+    for sentence in sentences(paragraph) {
+        for piece in hard_split(sentence, limit) {
+            if current.chars().count() + piece.chars().count() > limit {
+                chunks.push(std::mem::take(&mut current));
+            }
+            current.push_str(piece);
+        }
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Split after each sentence end, keeping the punctuation and following spaces
+fn sentences(paragraph: &str) -> Vec<&str> {
+    let mut result = vec![];
+    let mut start = 0;
+    let mut chars = paragraph.char_indices().peekable();
+
+    while let Some((_, ch)) = chars.next() {
+        if !SENTENCE_ENDS.contains(&ch) {
+            continue;
+        }
+        // "..." or "?!" belong to the same sentence end
+        while let Some(&(_, next)) = chars.peek() {
+            if SENTENCE_ENDS.contains(&next) || next.is_whitespace() {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let end = chars.peek().map_or(paragraph.len(), |&(i, _)| i);
+        result.push(&paragraph[start..end]);
+        start = end;
+    }
+    if start < paragraph.len() {
+        result.push(&paragraph[start..]);
+    }
+
+    result
+}
+
+/// Split a single over-long sentence at whitespace, or anywhere as a last resort
+fn hard_split(sentence: &str, limit: usize) -> Vec<&str> {
+    let mut result = vec![];
+    let mut rest = sentence;
+
+    while rest.chars().count() > limit {
+        let cut = rest.char_indices().nth(limit).unwrap().0;
+        let cut = match rest[..cut].rfind(char::is_whitespace) {
+            Some(space) if space > 0 => space + rest[space..].chars().next().unwrap().len_utf8(),
+            _ => cut,
+        };
+        result.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    result.push(rest);
+
+    result
+}
+
+impl Translation {
+    /// Translate `text` paragraph by paragraph, sending each chunk through `translate_chunk`
+    pub fn new<F, E>(text: &str, to: Lang, mut translate_chunk: F) -> Result<Translation, E>
+    where
+        F: FnMut(&str) -> Result<String, E>,
+    {
+        let parts = split(text, MAX_CHUNK_CHARS)
+            .into_iter()
+            .map(|(source, chunks)| {
+                let translations = chunks
+                    .iter()
+                    .map(|c| translate_chunk(c))
+                    .collect::<Result<Vec<_>, E>>()?;
+                Ok((source, translations))
+            })
+            .collect::<Result<Vec<_>, E>>()?;
+
+        Ok(Translation::from_parts(parts, to))
+    }
+
+    /// Reassemble source paragraphs and the translations of their chunks, as given by `split`
+    pub fn from_parts(parts: Vec<(String, Vec<String>)>, to: Lang) -> Translation {
+        // scripts without spaces between words are joined back without them
+        let glue = match to {
+            Lang::ZhChs | Lang::ZhCht | Lang::Ja | Lang::Th => "",
+            _ => " ",
+        };
+
+        let paragraphs = parts
+            .into_iter()
+            .map(|(source, translations)| Paragraph {
+                source,
+                translation: translations
+                    .iter()
+                    .map(|t| t.trim())
+                    .collect::<Vec<_>>()
+                    .join(glue),
+            })
+            .collect();
+
+        Translation { paragraphs }
+    }
+
+    /// Explain the translation using a formatter
+    pub fn explain(&self, fmt: &dyn Formatter) -> String {
+        fmt.translation(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(vec!["One. ", "Two! "], chunk("One. Two! ", 6));
+        assert_eq!(
+            vec!["第一句。第二句。", "第三句。"],
+            chunk("第一句。第二句。第三句。", 8)
+        );
+    }
+
+    #[test]
+    fn test_split_paragraphs() {
+        let split = split("Hello world.\n\nWhat a day... Indeed.", 16);
+        assert_eq!(3, split.len());
+        assert_eq!(vec!["Hello world."], split[0].1);
+        assert!(split[1].1.is_empty());
+        assert_eq!(vec!["What a day... ", "Indeed."], split[2].1);
+    }
+
+    #[test]
+    fn test_split_wrapped() {
+        let text = "  The quick brown fox jumps over\n  the lazy dog. It barks\n  back.\n\n第一段的\n第二行。";
+        assert_eq!(
+            vec![
+                (
+                    "The quick brown fox jumps over the lazy dog. It barks back.".to_owned(),
+                    vec![
+                        "The quick brown fox jumps over the lazy dog. ".to_owned(),
+                        "It barks back.".to_owned()
+                    ]
+                ),
+                (String::new(), vec![]),
+                (
+                    "第一段的第二行。".to_owned(),
+                    vec!["第一段的第二行。".to_owned()]
+                ),
+            ],
+            split(text, 50)
+        );
+    }
+
+    #[test]
+    fn test_split_long_sentence() {
+        assert_eq!(vec!["aaaa ", "bbbb ", "cc"], chunk("aaaa bbbb cc", 6));
+        assert_eq!(vec!["abcd", "ef"], chunk("abcdef", 4));
+    }
+
+    #[test]
+    fn test_translation_aligned() {
+        let translation = Translation::new("One. Two.\n\nThree.", Lang::ZhChs, |c| {
+            Ok::<_, ()>(format!("<{}>", c.trim()))
+        })
+        .unwrap();
+        assert_eq!(
+            vec!["<One. Two.>", "", "<Three.>"],
+            translation
+                .paragraphs
+                .iter()
+                .map(|p| p.translation.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("Three.", translation.paragraphs[2].source);
+    }
+}
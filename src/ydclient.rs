@@ -1,19 +1,19 @@
 //! ydclient is client wrapper for Client
 
 use super::ydresponse::YdResponse;
-use crate::lang::is_chinese;
+use crate::backend::{Backend, Backends};
+use crate::lang::Lang;
+use crate::nonblocking::{self, block_on};
+use crate::translate::Translation;
 use log::debug;
 use md5::{Digest, Md5};
 use once_cell::sync::Lazy;
 use rand::{thread_rng, Rng};
-use reqwest::blocking::Client;
-use reqwest::header::{REFERER, USER_AGENT};
-use reqwest::Url;
+use reqwest::Client;
 use serde_json::{self, Error as SerdeError};
 use std::env::var;
 use std::error::Error;
 use std::fmt::{self, Debug};
-use std::io::Read;
 
 const NEW_API_KEY: Option<&str> = option_env!("YD_NEW_APP_KEY");
 const NEW_APP_SEC: Option<&str> = option_env!("YD_NEW_APP_SEC");
@@ -26,10 +26,17 @@ static NEW_API_KEY_RT: Lazy<String> =
 static NEW_APP_SEC_RT: Lazy<String> =
     Lazy::new(|| var("YD_NEW_APP_SEC").unwrap_or_else(|_| String::from("ydcv-rs")));
 
+/// Error type of the async API, which can cross threads
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Errors of a lookup besides those of the HTTP client and the JSON decoder
 #[derive(Debug)]
-enum YdClientErr {
-    NewAndOldAPIError(String, String),
+pub enum YdClientErr {
+    NewAndOldAPIError(BoxError, BoxError),
     NewApiValueError,
+    UnsupportedLangPair(Lang, Lang),
+    ApiError(String),
+    Cancelled,
 }
 
 impl fmt::Display for YdClientErr {
@@ -39,142 +46,178 @@ impl fmt::Display for YdClientErr {
             YdClientErr::NewAndOldAPIError(new_api_err, old_api_err) => write!(f, "{}\n{}", old_api_err, new_api_err),
             // The error returned by not finding the variables YD_NEW_APP_KEY and YD_NEW_APP_SEC
             YdClientErr::NewApiValueError => write!(f, "New API value Error! Please make sure YD_NEW_APP_KEY and YD_NEW_APP_SEC Environment Variables is set!"),
+            // The youdao.com result page only serves the English <-> Chinese dictionary
+            YdClientErr::UnsupportedLangPair(from, to) => write!(f, "The youdao.com backend only supports English <-> Chinese lookups, not {} -> {}", from, to),
+            // The openapi answered, but with a non-zero errorCode
+            YdClientErr::ApiError(code) => write!(f, "The openapi returned error code {}", code),
+            // The lookup was cancelled before it finished
+            YdClientErr::Cancelled => write!(f, "The lookup was cancelled"),
         }
     }
 }
 
 impl Error for YdClientErr {}
 
-/// Wrapper trait on `reqwest::Client`
+/// Wrapper trait on `reqwest::Client` and `Backends`, blocking on the async API of `Backends`
 pub trait YdClient {
-    /// lookup a word on YD and returns a `YdPreponse`
+    /// lookup a word on YD and returns a `YdResponse`
     ///
     /// # Examples
     ///
     /// lookup "hello" and compare the result:
     ///
+    /// ```no_run
+    /// use reqwest::Client;
+    /// use ydcv::YdClient;
+    ///
+    /// assert_eq!("hello", Client::new().lookup_word("hello", false).unwrap().query());
     /// ```
-    /// assert_eq!("YdResponse('hello')",
-    ///        format!("{}", Client::new().lookup_word("hello").unwrap()));
-    /// ```
-    fn lookup_word(&mut self, word: &str, raw: bool) -> Result<YdResponse, Box<dyn Error>>;
+    fn lookup_word(&mut self, word: &str, raw: bool) -> Result<YdResponse, Box<dyn Error>> {
+        self.lookup_word_lang(word, Lang::Auto, Lang::Auto, raw)
+    }
+
+    /// lookup a word on YD translating from `from` into `to`
+    fn lookup_word_lang(
+        &mut self,
+        word: &str,
+        from: Lang,
+        to: Lang,
+        raw: bool,
+    ) -> Result<YdResponse, Box<dyn Error>>;
     fn decode_result(&mut self, result: &str) -> Result<YdResponse, SerdeError>;
+
+    /// translate a whole text through the openapi, keeping its paragraphs aligned
+    fn translate_text(
+        &mut self,
+        text: &str,
+        from: Lang,
+        to: Lang,
+    ) -> Result<Translation, Box<dyn Error>>;
 }
 
-/// Implement wrapper client trait on `reqwest::Client`
+/// Implement wrapper client trait on `reqwest::Client`, using it for every backend
 impl YdClient for Client {
     fn decode_result(&mut self, result: &str) -> Result<YdResponse, SerdeError> {
-        let pretty_json = serde_json::from_str::<YdResponse>(result)
-            .and_then(|v| serde_json::to_string_pretty(&v));
-        debug!(
-            "Recieved JSON {}",
-            match pretty_json {
-                Ok(r) => r,
-                Err(_) => result.to_owned(),
-            }
-        );
-        serde_json::from_str(result)
+        decode_result(result)
     }
 
-    #[cfg(all(not(feature = "native-tls"), not(feature = "rustls")))]
-    fn lookup_word(&mut self, word: &str, raw: bool) -> Result<YdResponse, Box<dyn Error>> {
-        panic!("https access has been disabled in this build of ydcv-rs");
+    fn lookup_word_lang(
+        &mut self,
+        word: &str,
+        from: Lang,
+        to: Lang,
+        raw: bool,
+    ) -> Result<YdResponse, Box<dyn Error>> {
+        Backends::with_client(self.clone()).lookup_word_lang(word, from, to, raw)
     }
 
-    /// lookup a word on YD and returns a `YdResponse`
-    #[cfg(any(feature = "native-tls", feature = "rustls"))]
-    fn lookup_word(&mut self, word: &str, raw: bool) -> Result<YdResponse, Box<dyn Error>> {
-        let body = lookup_word(word, self);
+    fn translate_text(
+        &mut self,
+        text: &str,
+        from: Lang,
+        to: Lang,
+    ) -> Result<Translation, Box<dyn Error>> {
+        Backends::with_client(self.clone()).translate_text(text, from, to)
+    }
+}
 
-        if let Err(old_api_err) = body {
-            let body = lookup_word_new_api(word, self);
+/// Implement wrapper client trait on `Backends`, with one client per backend
+impl YdClient for Backends {
+    fn decode_result(&mut self, result: &str) -> Result<YdResponse, SerdeError> {
+        decode_result(result)
+    }
 
-            if let Err(new_api_err) = body {
-                return Err(Box::new(YdClientErr::NewAndOldAPIError(
-                    new_api_err.to_string(),
-                    old_api_err.to_string(),
-                )));
-            }
+    #[cfg(all(not(feature = "native-tls"), not(feature = "rustls")))]
+    fn lookup_word_lang(
+        &mut self,
+        word: &str,
+        from: Lang,
+        to: Lang,
+        raw: bool,
+    ) -> Result<YdResponse, Box<dyn Error>> {
+        panic!("https access has been disabled in this build of ydcv-rs");
+    }
 
-            let body = body.unwrap();
+    #[cfg(all(not(feature = "native-tls"), not(feature = "rustls")))]
+    fn translate_text(
+        &mut self,
+        text: &str,
+        from: Lang,
+        to: Lang,
+    ) -> Result<Translation, Box<dyn Error>> {
+        panic!("https access has been disabled in this build of ydcv-rs");
+    }
 
-            if raw {
-                YdResponse::new_raw(body).map_err(Into::into)
-            } else {
-                self.decode_result(&body).map_err(Into::into)
-            }
-        } else {
-            let body = body?;
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    fn translate_text(
+        &mut self,
+        text: &str,
+        from: Lang,
+        to: Lang,
+    ) -> Result<Translation, Box<dyn Error>> {
+        block_on(self.translate(text, from, to)).map_err(|e| e as Box<dyn Error>)
+    }
 
-            YdResponse::from_html(&body, word).map_err(Into::into)
-        }
+    /// lookup a word on YD and returns a `YdResponse`
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    fn lookup_word_lang(
+        &mut self,
+        word: &str,
+        from: Lang,
+        to: Lang,
+        raw: bool,
+    ) -> Result<YdResponse, Box<dyn Error>> {
+        block_on(self.lookup(word, from, to, raw)).map_err(|e| e as Box<dyn Error>)
     }
 }
 
-fn lookup_word(word: &str, client: &Client) -> Result<String, Box<dyn Error>> {
-    let url = api(
-        "https://www.youdao.com/result",
-        &[("word", word), ("lang", "en")],
-    )?;
-
-    let mut body = String::new();
-    client
-        .get(url)
-        .header(REFERER, "https://www.youdao.com")
-        .header(
-            USER_AGENT,
-            "Mozilla/5.0 (X11; AOSC OS; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/117.0",
-        )
-        .send()?
-        .read_to_string(&mut body)?;
-
-    Ok(body)
+impl Backends {
+    /// lookup a word on the backend called `name` only, without falling back to the others
+    pub fn lookup_on(
+        &self,
+        name: &str,
+        word: &str,
+        from: Lang,
+        to: Lang,
+    ) -> Result<YdResponse, Box<dyn Error>> {
+        block_on(self.lookup_backend(name, word, from, to)).map_err(|e| e as Box<dyn Error>)
+    }
 }
 
-fn lookup_word_new_api(word: &str, client: &Client) -> Result<String, Box<dyn Error>> {
-    let (new_api_key, new_app_sec) =
-        if let (Some(new_api_key), Some(new_app_sec)) = (NEW_API_KEY, NEW_APP_SEC) {
-            (new_api_key, new_app_sec)
-        } else if NEW_API_KEY_RT.as_str() != "ydcv-rs" && NEW_APP_SEC_RT.as_str() != "ydcv-rs" {
-            (NEW_API_KEY_RT.as_str(), NEW_APP_SEC_RT.as_str())
-        } else {
-            return Err(Box::new(YdClientErr::NewApiValueError));
-        };
-
-    let to = get_translation_lang(word);
-    let salt = get_salt();
-    let sign = get_sign(new_api_key, word, &salt, new_app_sec);
-
-    let url = api(
-        "https://openapi.youdao.com/api",
-        &[
-            ("appKey", new_api_key),
-            ("q", word),
-            ("from", "auto"),
-            ("to", to),
-            ("salt", &salt),
-            ("sign", &sign),
-        ],
-    )?;
-
-    let mut body = String::new();
-    client
-        .get(url)
-        // .header(Connection::close())
-        .send()?
-        .read_to_string(&mut body)?;
-
-    Ok(body)
+pub(crate) fn decode_result(result: &str) -> Result<YdResponse, SerdeError> {
+    let pretty_json =
+        serde_json::from_str::<YdResponse>(result).and_then(|v| serde_json::to_string_pretty(&v));
+    debug!(
+        "Recieved JSON {}",
+        match pretty_json {
+            Ok(r) => r,
+            Err(_) => result.to_owned(),
+        }
+    );
+    serde_json::from_str(result)
 }
 
-fn api(url: &str, query: &[(&str, &str)]) -> Result<Url, Box<dyn Error>> {
-    let mut url = Url::parse(url)?;
-    url.query_pairs_mut().extend_pairs(query.iter());
+/// Fetch the youdao.com result page of `word`
+pub fn lookup_word(
+    word: &str,
+    from: Lang,
+    to: Lang,
+    backend: &Backend,
+) -> Result<String, Box<dyn Error>> {
+    block_on(nonblocking::lookup_word(word, from, to, backend)).map_err(|e| e as Box<dyn Error>)
+}
 
-    Ok(url)
+pub(crate) fn new_api_keys() -> Result<(&'static str, &'static str), YdClientErr> {
+    if let (Some(new_api_key), Some(new_app_sec)) = (NEW_API_KEY, NEW_APP_SEC) {
+        Ok((new_api_key, new_app_sec))
+    } else if NEW_API_KEY_RT.as_str() != "ydcv-rs" && NEW_APP_SEC_RT.as_str() != "ydcv-rs" {
+        Ok((NEW_API_KEY_RT.as_str(), NEW_APP_SEC_RT.as_str()))
+    } else {
+        Err(YdClientErr::NewApiValueError)
+    }
 }
 
-fn get_sign(api_key: &str, word: &str, salt: &str, app_sec: &str) -> String {
+pub(crate) fn get_sign(api_key: &str, word: &str, salt: &str, app_sec: &str) -> String {
     let sign_no_md5 = format!("{}{}{}{}", api_key, word, &salt, app_sec);
 
     let mut hasher = Md5::new();
@@ -186,26 +229,21 @@ fn get_sign(api_key: &str, word: &str, salt: &str, app_sec: &str) -> String {
     sign
 }
 
-fn get_salt() -> String {
+pub(crate) fn get_salt() -> String {
     let mut rng = thread_rng();
     let rand_int = rng.gen_range(1..65536);
 
     rand_int.to_string()
 }
 
-fn get_translation_lang(word: &str) -> &str {
-    let word_is_chinese = is_chinese(word);
-
-    if word_is_chinese {
-        "EN"
-    } else {
-        "zh-CHS"
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::NetOptions;
+    use crate::config::Config;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
 
     #[test]
     fn test_lookup_word_0() {
@@ -230,4 +268,74 @@ mod tests {
             format!("{}", Client::new().lookup_word("<+*>?_", false).unwrap())
         );
     }
+
+    #[test]
+    fn test_lookup_word_unsupported_pair() {
+        let backends = Backends::with_client(Client::new());
+        let err = lookup_word("hello", Lang::En, Lang::Ja, &backends.scraper).unwrap_err();
+        assert_eq!(
+            "The youdao.com backend only supports English <-> Chinese lookups, not en -> ja",
+            err.to_string()
+        );
+    }
+
+    /// Answer one request on a local port with `body`, returning the base URL
+    /// and a handle yielding the request head that was received
+    fn mock_server(body: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/result", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = vec![];
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                head.extend_from_slice(&buf[..n]);
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            String::from_utf8(head).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_lookup_word_configured_endpoint() {
+        let (url, server) =
+            mock_server(r#"<html><div class="no-data-prompt">no result</div></html>"#);
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [backends.scraper]
+            proxy = "direct"
+            url = "{}"
+            user_agent = "ydcv-test"
+            headers = {{ X-Mirror-Token = "secret" }}
+            "#,
+            url
+        ))
+        .unwrap();
+        let mut backends = Backends::new(&config, &NetOptions::default()).unwrap();
+
+        let result = backends.lookup_word("hello", false).unwrap();
+        assert!(!result.is_ok());
+
+        let head = server.join().unwrap().to_ascii_lowercase();
+        assert!(
+            head.starts_with("get /result?word=hello&lang=en "),
+            "{}",
+            head
+        );
+        assert!(head.contains("user-agent: ydcv-test\r\n"), "{}", head);
+        assert!(head.contains("x-mirror-token: secret\r\n"), "{}", head);
+        assert!(
+            head.contains("referer: https://www.youdao.com\r\n"),
+            "{}",
+            head
+        );
+    }
 }